@@ -0,0 +1,129 @@
+//! Times decoding every brush in an ABR file, first reading through a
+//! `BufReader<File>`, then from memory with `abr::open_slice` on a
+//! memory-mapped copy of the file. The quickest of a few runs each way is
+//! reported.
+//!
+//!     cargo run --release --example decode_bench [FILE.abr]
+//!
+//! Without a file, a synthetic one of about 600MB, of run-length compressed
+//! brushes, is written to the temp directory first (and removed after). If
+//! the file given doesn't exist, the synthetic one is written there and
+//! kept.
+
+extern crate abrupng;
+extern crate memmap2;
+
+use abrupng::abr;
+use memmap2::Mmap;
+use std::env;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+/// How many times to decode the file each way.
+const RUNS: usize = 3;
+
+fn main() {
+    let (path, temporary) = match env::args().nth(1) {
+        Some(path) => (PathBuf::from(path), false),
+        None => (env::temp_dir().join("abrupng-decode-bench.abr"), true),
+    };
+    if temporary || !path.exists() {
+        println!("Writing {}...", path.display());
+        write_synthetic(&path);
+    }
+    let len = fs::metadata(&path).expect("couldn't stat file").len();
+    println!("{}: {:.1} MB", path.display(), len as f64 / 1e6);
+
+    // Once first, so both ways start with the file in the page cache.
+    decode_file(&path);
+
+    time("BufReader<File>", len, || decode_file(&path));
+    time("mmap + open_slice", len, || {
+        let file = File::open(&path).expect("couldn't open file");
+        let map = unsafe { Mmap::map(&file) }.expect("couldn't map file");
+        decode(abr::open_slice(&map).expect("couldn't open ABR"))
+    });
+
+    if temporary {
+        let _ = fs::remove_file(&path);
+    }
+}
+
+/// Decodes every brush in the file at `path`, reading through a
+/// `BufReader`. Returns how many brushes there were and how many bytes of
+/// samples they had.
+fn decode_file(path: &Path) -> (usize, usize) {
+    let file = File::open(path).expect("couldn't open file");
+    decode(abr::open(BufReader::new(file)).expect("couldn't open ABR"))
+}
+
+/// Decodes every brush in `brushes`. Returns how many there were and how
+/// many bytes of samples they had.
+fn decode<R: std::io::Read + std::io::Seek>(brushes: abr::Brushes<R>) -> (usize, usize) {
+    let mut count = 0;
+    let mut bytes = 0;
+    for brush in brushes {
+        count += 1;
+        if let Ok(abr::Brush::Image(brush)) = brush {
+            bytes += brush.data.len();
+        }
+    }
+    (count, bytes)
+}
+
+/// Runs `decode` a few times, and prints how long the quickest run took
+/// for a file `len` bytes long.
+fn time<F: Fn() -> (usize, usize)>(name: &str, len: u64, decode: F) {
+    let mut best = None;
+    let mut counts = (0, 0);
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        counts = decode();
+        let secs = start.elapsed().as_secs_f64();
+        best = Some(best.map_or(secs, |best: f64| best.min(secs)));
+    }
+    let secs = best.unwrap_or(0.0);
+    println!("{:<18} {} brushes, {:.1} MB of samples in {:.3}s ({:.0} MB/s of file)",
+             name, counts.0, counts.1 as f64 / 1e6, secs, len as f64 / 1e6 / secs);
+}
+
+/// Writes a synthetic ABR file to `path`: 1024×1024 brushes of grainy noise
+/// that's stored run-length compressed, as most real brushes are.
+fn write_synthetic(path: &Path) {
+    let size = 1024u32;
+    let mut seed = 0x2545_f491u32;
+    let mut value = 0;
+    let brushes = (0..1200)
+        .map(|_| {
+            let data = (0..size * size)
+                .map(|_| {
+                    // xorshift32
+                    seed ^= seed << 13;
+                    seed ^= seed >> 17;
+                    seed ^= seed << 5;
+                    // Change value one time in four, for runs long enough
+                    // to be worth compressing.
+                    if seed & 3 == 0 {
+                        value = (seed >> 8) as u8;
+                    }
+                    value
+                })
+                .collect();
+            abr::ImageBrush {
+                width: size,
+                height: size,
+                depth: 8,
+                data,
+                uuid: None,
+                name: None,
+                spacing: None,
+                antialias: None,
+                compressed: false,
+            }
+        })
+        .collect::<Vec<_>>();
+    abr::save(path, &brushes, &abr::WriteOptions { omit_presets: true })
+        .expect("couldn't write synthetic ABR");
+}
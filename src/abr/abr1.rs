@@ -1,6 +1,6 @@
 use std::io::{self, Read, Seek, SeekFrom};
 use super::byteorder::{BigEndian, ReadBytesExt};
-use super::{ImageBrush, OpenError, BrushError};
+use super::{Brush, BrushHeader, BrushRecord, ComputedBrush, ImageBrush, OpenError, BrushError};
 use super::util;
 
 /// Decoder state for ABR1-like formats (versions 1 and 2).
@@ -19,18 +19,23 @@ pub fn open<R: Read + Seek>(mut rdr: R,
     Ok(Decoder { rdr, version, count, next_brush_pos: cur_pos })
 }
 
+pub fn next_offset<R>(dec: &Decoder<R>) -> Option<u64> {
+    if dec.count == 0 { None } else { Some(dec.next_brush_pos) }
+}
+
 pub fn next_brush<R: Read + Seek>(dec: &mut Decoder<R>)
-                                  -> Option<Result<ImageBrush, BrushError>> {
+                                  -> Option<Result<Brush, BrushError>> {
     if dec.count == 0 {
         return None;
     }
 
     dec.count -= 1;
 
-    Some(match do_brush_head(dec) {
+    let brush_pos = dec.next_brush_pos;
+    Some(match do_brush_head(dec, brush_pos) {
         Ok(res) => {
             dec.next_brush_pos = res.next_brush_pos;
-            do_brush_body(dec)
+            do_brush_body(dec, res.next_brush_pos)
         }
         Err(e) => {
             // We didn't get the next brush's position, so we can't resume on
@@ -41,15 +46,102 @@ pub fn next_brush<R: Read + Seek>(dec: &mut Decoder<R>)
     })
 }
 
+/// Finds where each of the brushes left to iterate over is, without reading
+/// them. A brush whose length can't be read ends the list.
+pub fn index<R: Read + Seek>(dec: &mut Decoder<R>) -> Vec<BrushRecord> {
+    let mut records = vec![];
+    let mut brush_pos = dec.next_brush_pos;
+    for _ in 0..dec.count {
+        match do_brush_head(dec, brush_pos) {
+            Ok(res) => {
+                let len = res.next_brush_pos - brush_pos;
+                records.push(BrushRecord { offset: brush_pos, len });
+                brush_pos = res.next_brush_pos;
+            }
+            Err(_) => break,
+        }
+    }
+    records
+}
+
+/// Reads the brush at `record`. This doesn't affect iteration.
+pub fn brush_at<R: Read + Seek>(dec: &mut Decoder<R>, record: &BrushRecord)
+                                -> Result<Brush, BrushError> {
+    let res = do_brush_head(dec, record.offset)?;
+    do_brush_body(dec, res.next_brush_pos)
+}
+
+/// Reads the header of the brush at `record`, without reading its samples.
+/// This doesn't affect iteration.
+pub fn header_at<R: Read + Seek>(dec: &mut Decoder<R>, record: &BrushRecord)
+                                 -> Result<BrushHeader, BrushError> {
+    let res = do_brush_head(dec, record.offset)?;
+    let ty = dec.rdr.read_u16::<BigEndian>()?;
+    match ty {
+        1 => {
+            let brush = do_computed_brush(dec)?;
+            Ok(BrushHeader {
+                record: *record,
+                computed: true,
+                width: brush.diameter as u32,
+                height: brush.diameter as u32,
+                depth: None,
+                compressed: false,
+                compressed_len: None,
+                samples_offset: None,
+                uuid: None,
+                name: brush.name,
+                spacing: Some(brush.spacing),
+                antialias: None,
+            })
+        }
+        2 => {
+            let header = do_image_header(dec)?;
+            let samples_offset = util::tell(&mut dec.rdr)?;
+            let compressed_len = if header.compressed {
+                Some(util::read_rle_len(&mut dec.rdr, header.height)?)
+            } else {
+                check_raw_size(dec, &header, res.next_brush_pos)?;
+                None
+            };
+            Ok(BrushHeader {
+                record: *record,
+                computed: false,
+                width: header.width,
+                height: header.height,
+                depth: Some(header.depth),
+                compressed: header.compressed,
+                compressed_len,
+                samples_offset: Some(samples_offset),
+                uuid: None,
+                name: header.name,
+                spacing: Some(header.spacing),
+                antialias: Some(header.antialias),
+            })
+        }
+        _ => Err(BrushError::UnsupportedBrushType { ty }),
+    }
+}
+
+/// Reads the brush `header` (from `header_at`) describes, without reading
+/// its header again, unless it's a computed brush, which is all header.
+/// This doesn't affect iteration.
+pub fn brush_from_header<R: Read + Seek>(dec: &mut Decoder<R>, header: &BrushHeader)
+                                         -> Result<Brush, BrushError> {
+    if header.computed {
+        return brush_at(dec, &header.record);
+    }
+    Ok(Brush::Image(util::read_image(&mut dec.rdr, header)?))
+}
+
 struct BrushHeadResult {
     next_brush_pos: u64,
 }
 
-/// Moves `dec` into position to read out the next brush with `do_brush_body`.
-/// Returns where the brush after this one is located.
-fn do_brush_head<R: Read + Seek>(dec: &mut Decoder<R>)
+/// Moves `dec` into position to read out the brush at `brush_pos` with
+/// `do_brush_body`. Returns where the brush after this one is located.
+fn do_brush_head<R: Read + Seek>(dec: &mut Decoder<R>, brush_pos: u64)
                                  -> Result<BrushHeadResult, io::Error> {
-    let brush_pos = dec.next_brush_pos;
     dec.rdr.seek(SeekFrom::Start(brush_pos))?;
 
     let len = dec.rdr.read_u16::<BigEndian>()? as u64;
@@ -59,29 +151,112 @@ fn do_brush_head<R: Read + Seek>(dec: &mut Decoder<R>)
     Ok(BrushHeadResult { next_brush_pos })
 }
 
-/// With `dec` positioned by `do_brush_head`, reads out a brush.
-fn do_brush_body<R: Read + Seek>(dec: &mut Decoder<R>) -> Result<ImageBrush, BrushError> {
+/// With `dec` positioned by `do_brush_head`, reads out a brush that ends at
+/// `end_pos`.
+fn do_brush_body<R: Read + Seek>(dec: &mut Decoder<R>, end_pos: u64)
+                                 -> Result<Brush, BrushError> {
     let ty = dec.rdr.read_u16::<BigEndian>()?;
-    if ty != 2 {
-        return Err(BrushError::UnsupportedBrushType { ty });
+    match ty {
+        1 => do_computed_brush(dec).map(Brush::Computed),
+        2 => do_sampled_brush(dec, end_pos).map(Brush::Image),
+        _ => Err(BrushError::UnsupportedBrushType { ty }),
     }
+}
 
+/// Reads the body of a computed brush (type 1).
+fn do_computed_brush<R: Read + Seek>(dec: &mut Decoder<R>) -> Result<ComputedBrush, BrushError> {
     let _misc = dec.rdr.read_u32::<BigEndian>()?;
-    let _spacing = dec.rdr.read_u16::<BigEndian>()?;
+    let spacing = dec.rdr.read_u16::<BigEndian>()?;
+
+    let name = if dec.version == 2 {
+        Some(util::read_unicode_string(&mut dec.rdr)?)
+    } else {
+        None
+    };
+
+    let diameter = dec.rdr.read_u16::<BigEndian>()?;
+    let roundness = dec.rdr.read_u16::<BigEndian>()?;
+    let angle = dec.rdr.read_i16::<BigEndian>()?;
+    let hardness = dec.rdr.read_u16::<BigEndian>()?;
+
+    Ok(ComputedBrush { diameter, roundness, angle, hardness, spacing, name })
+}
+
+/// What the header of a sampled brush says.
+struct ImageHeader {
+    name: Option<String>,
+    spacing: u16,
+    antialias: bool,
+    width: u32,
+    height: u32,
+    depth: u16,
+    compressed: bool,
+}
 
-    if dec.version == 2 {
-        // Skip over a length-prefixed UCS2 String
-        let len = dec.rdr.read_u32::<BigEndian>()? as i64;
-        let len_in_bytes = 2 * len;
-        dec.rdr.seek(SeekFrom::Current(len_in_bytes))?;
+/// Reads the body of a sampled brush (type 2), which ends at `end_pos`.
+fn do_sampled_brush<R: Read + Seek>(dec: &mut Decoder<R>, end_pos: u64)
+                                    -> Result<ImageBrush, BrushError> {
+    let header = do_image_header(dec)?;
+    let size = image_size(&header);
+
+    let data = if header.compressed {
+        util::read_rle_data(&mut dec.rdr, header.height, size)?
+    } else {
+        check_raw_size(dec, &header, end_pos)?;
+        let mut v = vec![0; size];
+        dec.rdr.read_exact(&mut v)?;
+        v
+    };
+
+    Ok(ImageBrush {
+        width: header.width,
+        height: header.height,
+        depth: header.depth,
+        data,
+        uuid: None,
+        name: header.name,
+        spacing: Some(header.spacing),
+        antialias: Some(header.antialias),
+        compressed: header.compressed,
+    })
+}
+
+/// Size of the brush's uncompressed samples.
+fn image_size(header: &ImageHeader) -> usize {
+    (header.width as usize) * (header.height as usize) * (header.depth as usize >> 3)
+}
+
+/// With `dec` positioned at the start of a brush's uncompressed samples,
+/// checks they fit before `end_pos`.
+fn check_raw_size<R: Read + Seek>(dec: &mut Decoder<R>, header: &ImageHeader, end_pos: u64)
+                                  -> Result<(), BrushError> {
+    if util::tell(&mut dec.rdr)? + image_size(header) as u64 > end_pos {
+        return Err(BrushError::MalformedHeader("image data overruns brush"));
     }
+    Ok(())
+}
 
-    let _antialiasing = dec.rdr.read_u8()?;
+/// Reads the header of a sampled brush (type 2), leaving `dec` at the start
+/// of its samples.
+fn do_image_header<R: Read + Seek>(dec: &mut Decoder<R>) -> Result<ImageHeader, BrushError> {
+    let _misc = dec.rdr.read_u32::<BigEndian>()?;
+    let spacing = dec.rdr.read_u16::<BigEndian>()?;
+
+    let name = if dec.version == 2 {
+        Some(util::read_unicode_string(&mut dec.rdr)?)
+    } else {
+        None
+    };
+
+    let antialias = dec.rdr.read_u8()? != 0;
 
     let top = dec.rdr.read_u16::<BigEndian>()?;
     let left = dec.rdr.read_u16::<BigEndian>()?;
     let bottom = dec.rdr.read_u16::<BigEndian>()?;
     let right = dec.rdr.read_u16::<BigEndian>()?;
+    if bottom < top || right < left {
+        return Err(BrushError::MalformedHeader("bad bounds"));
+    }
 
     let _topl = dec.rdr.read_u32::<BigEndian>()?;
     let _leftl = dec.rdr.read_u32::<BigEndian>()?;
@@ -89,23 +264,19 @@ fn do_brush_body<R: Read + Seek>(dec: &mut Decoder<R>) -> Result<ImageBrush, Bru
     let _rightl = dec.rdr.read_u32::<BigEndian>()?;
 
     let depth = dec.rdr.read_u16::<BigEndian>()?;
-    if depth != 8 {
+    if depth != 8 && depth != 16 {
         return Err(BrushError::UnsupportedBitDepth { depth });
     }
 
     let compressed = dec.rdr.read_u8()? != 0;
 
-    let width = (right - left) as u32;
-    let height = (bottom - top) as u32;
-    let size = (width as usize) * (height as usize) * (depth as usize >> 3);
-
-    let data = if compressed {
-        util::read_rle_data(&mut dec.rdr, height, size)?
-    } else {
-        let mut v = vec![0; size];
-        dec.rdr.read_exact(&mut v)?;
-        v
-    };
-
-    Ok(ImageBrush { width, height, depth, data })
+    Ok(ImageHeader {
+        name,
+        spacing,
+        antialias,
+        width: (right - left) as u32,
+        height: (bottom - top) as u32,
+        depth,
+        compressed,
+    })
 }
@@ -1,63 +1,177 @@
 use std::io::{self, Read, Seek, SeekFrom};
 use super::byteorder::{BigEndian, ReadBytesExt};
-use super::{ImageBrush, OpenError, BrushError};
+use super::{Block, Brush, BrushHeader, BrushRecord, ImageBrush, OpenError, BrushError,
+            DescriptorError, PatternError};
+use super::desc::{self, Descriptor};
+use super::pattern::{self, Pattern};
 use super::util;
 
-/// Decoder state for ABR6-like formats (versions 6 and 10).
+/// Decoder state for ABR6-like formats (versions 6, 7, 9 and 10).
 pub struct Decoder<R> {
     rdr: R,
     #[allow(dead_code)]
     version: u16,
-    subversion: u16,
+    layout: Layout,
     sample_section_end: u64,
     next_brush_pos: u64,
+    desc_section: Option<Section>,
+    patt_section: Option<Section>,
+    blocks: Vec<Block>,
 }
 
-pub fn open<R: Read + Seek>(mut rdr: R, version: u16, subversion: u16)
+/// How the header of each sampled brush is laid out. This is what the
+/// subversion determines.
+#[derive(Copy, Clone)]
+pub enum Layout {
+    /// Subversion 1: the UUID is followed by the bounds as u16s.
+    Short,
+    /// Subversion 2: the UUID is followed by 264 bytes of unknown meaning
+    /// (GIMP and Krita skip them too). They're read and ignored; all that's
+    /// checked is that they and the rest of the header fit in the brush.
+    Long,
+    /// Unknown subversion: try both and use whichever looks right.
+    Guess,
+}
+
+/// Bytes between the UUID and the u32 bounds in the `Short` layout.
+const SHORT_EXTRA: usize = 10;
+
+/// Bytes between the UUID and the u32 bounds in the `Long` layout.
+const LONG_EXTRA: usize = 264;
+
+/// Location of the data in an 8BIM/8B64 block.
+#[derive(Copy, Clone)]
+struct Section {
+    start: u64,
+    len: u64,
+}
+
+pub fn open<R: Read + Seek>(mut rdr: R, version: u16, layout: Layout)
                             -> Result<Decoder<R>, OpenError> {
-    // Find the sample section
-    loop {
-        let mut buf = [0; 4];
+    let mut pos = util::tell(&mut rdr)?;
+    let file_end = rdr.seek(SeekFrom::End(0))?;
 
-        rdr.read_exact(&mut buf)?;
-        if buf == &b"8bim"[..] {
-            return Err(OpenError::Found8bim);
-        }
+    // Walk the 8BIM blocks, remembering where the ones we care about are.
+    let mut samp_section = None;
+    let mut desc_section = None;
+    let mut patt_section = None;
+    let mut blocks = vec![];
+    while pos + 12 <= file_end {
+        rdr.seek(SeekFrom::Start(pos))?;
+
+        let mut signature = [0; 4];
+        rdr.read_exact(&mut signature)?;
+        let mut key = [0; 4];
+        rdr.read_exact(&mut key)?;
 
-        rdr.read_exact(&mut buf)?;
-        if buf == &b"samp"[..] {
-            break;
+        // The signature says how wide the length field is. Photoshop mostly
+        // writes 8BIM, but the other spellings show up too.
+        let len = match &signature {
+            b"8BIM" | b"8bim" | b"MeSa" => rdr.read_u32::<BigEndian>()? as u64,
+            b"8B64" | b"8b64" => rdr.read_u64::<BigEndian>()?,
+            _ => {
+                // We've lost track of the blocks. If we already have the
+                // brushes, call it trailing junk; otherwise give up.
+                if samp_section.is_some() {
+                    break;
+                }
+                let signature = String::from_utf8_lossy(&signature[..]).into_owned();
+                return Err(OpenError::BadBlockSignature { signature });
+            }
+        };
+        let start = util::tell(&mut rdr)?;
+        // Don't trust the length past the end of the file.
+        let len = len.min(file_end.saturating_sub(start));
+        let section = Section { start, len };
+        blocks.push(Block {
+            signature: String::from_utf8_lossy(&signature[..]).into_owned(),
+            key: String::from_utf8_lossy(&key[..]).into_owned(),
+            offset: start,
+            len,
+        });
+
+        match &key {
+            b"samp" if samp_section.is_none() => samp_section = Some(section),
+            b"desc" if desc_section.is_none() => desc_section = Some(section),
+            b"patt" if patt_section.is_none() => patt_section = Some(section),
+            _ => (),
         }
 
-        let len = rdr.read_u32::<BigEndian>()?;
-        rdr.seek(SeekFrom::Current(len as i64))?;
+        pos = section.start + len;
     }
 
-    let len = rdr.read_u32::<BigEndian>()? as u64;
-    let cur = util::tell(&mut rdr)?;
-    let sample_section_end = cur + len;
+    // With no sample section, there are just no brushes.
+    let samp_section = samp_section.unwrap_or(Section { start: 0, len: 0 });
 
     Ok(Decoder {
         rdr,
         version,
-        subversion,
-        sample_section_end,
-        next_brush_pos: cur,
+        layout,
+        sample_section_end: samp_section.start + samp_section.len,
+        next_brush_pos: samp_section.start,
+        desc_section,
+        patt_section,
+        blocks,
     })
 }
 
+pub fn blocks<R>(dec: &Decoder<R>) -> &[Block] {
+    &dec.blocks
+}
+
+/// Reads the descriptor in the `desc` section, if there is one.
+pub fn read_descriptor<R: Read + Seek>(dec: &mut Decoder<R>)
+                                       -> Result<Option<Descriptor>, DescriptorError> {
+    let section = match dec.desc_section {
+        Some(section) => section,
+        None => return Ok(None),
+    };
+
+    dec.rdr.seek(SeekFrom::Start(section.start))?;
+    let mut rdr = (&mut dec.rdr).take(section.len);
+    let _desc_version = rdr.read_u32::<BigEndian>()?;
+    Ok(Some(desc::read_descriptor(&mut rdr)?))
+}
+
+/// Reads the patterns in the `patt` section, if there is one.
+pub fn read_patterns<R: Read + Seek>(dec: &mut Decoder<R>) -> Vec<Result<Pattern, PatternError>> {
+    match dec.patt_section {
+        Some(section) => {
+            pattern::read_patterns(&mut dec.rdr, section.start, section.start + section.len)
+        }
+        None => vec![],
+    }
+}
+
+pub fn count_patterns<R: Read + Seek>(dec: &mut Decoder<R>) -> usize {
+    match dec.patt_section {
+        Some(section) => {
+            pattern::find_patterns(&mut dec.rdr, section.start, section.start + section.len).len()
+        }
+        None => 0,
+    }
+}
+
+pub fn next_offset<R>(dec: &Decoder<R>) -> Option<u64> {
+    if dec.next_brush_pos < dec.sample_section_end {
+        Some(dec.next_brush_pos)
+    } else {
+        None
+    }
+}
 
 pub fn next_brush<R: Read + Seek>(dec: &mut Decoder<R>)
-                                  -> Option<Result<ImageBrush, BrushError>> {
+                                  -> Option<Result<Brush, BrushError>> {
     // Is iteration over?
     if dec.next_brush_pos >= dec.sample_section_end {
         return None;
     }
 
-    Some(match do_brush_head(dec) {
+    let brush_pos = dec.next_brush_pos;
+    Some(match do_brush_head(dec, brush_pos) {
         Ok(res) => {
             dec.next_brush_pos = res.next_brush_pos;
-            do_brush_body(dec)
+            do_brush_body(dec, res.end_pos).map(Brush::Image)
         }
         Err(e) => {
             // We didn't get the next brush's position, so we can't resume on
@@ -68,15 +182,77 @@ pub fn next_brush<R: Read + Seek>(dec: &mut Decoder<R>)
     })
 }
 
+/// Finds where each of the brushes left to iterate over is, without reading
+/// them. A brush whose length can't be read ends the list.
+pub fn index<R: Read + Seek>(dec: &mut Decoder<R>) -> Vec<BrushRecord> {
+    let mut records = vec![];
+    let mut brush_pos = dec.next_brush_pos;
+    while brush_pos < dec.sample_section_end {
+        match do_brush_head(dec, brush_pos) {
+            Ok(res) => {
+                records.push(BrushRecord { offset: brush_pos, len: res.end_pos - brush_pos });
+                brush_pos = res.next_brush_pos;
+            }
+            Err(_) => break,
+        }
+    }
+    records
+}
+
+/// Reads the brush at `record`. This doesn't affect iteration.
+pub fn brush_at<R: Read + Seek>(dec: &mut Decoder<R>, record: &BrushRecord)
+                                -> Result<Brush, BrushError> {
+    let res = do_brush_head(dec, record.offset)?;
+    do_brush_body(dec, res.end_pos).map(Brush::Image)
+}
+
+/// Reads the header of the brush at `record`, without reading its samples.
+/// This doesn't affect iteration.
+pub fn header_at<R: Read + Seek>(dec: &mut Decoder<R>, record: &BrushRecord)
+                                 -> Result<BrushHeader, BrushError> {
+    let res = do_brush_head(dec, record.offset)?;
+    let header = do_image_header(dec, res.end_pos)?;
+    let samples_offset = util::tell(&mut dec.rdr)?;
+    let compressed_len = if header.compressed {
+        Some(util::read_rle_len(&mut dec.rdr, header.height)?)
+    } else {
+        check_raw_size(dec, &header, res.end_pos)?;
+        None
+    };
+
+    Ok(BrushHeader {
+        record: *record,
+        computed: false,
+        width: header.width,
+        height: header.height,
+        depth: Some(header.depth),
+        compressed: header.compressed,
+        compressed_len,
+        samples_offset: Some(samples_offset),
+        uuid: Some(header.uuid),
+        name: None,
+        spacing: None,
+        antialias: None,
+    })
+}
+
+/// Reads the brush `header` (from `header_at`) describes, without reading
+/// its header again. This doesn't affect iteration.
+pub fn brush_from_header<R: Read + Seek>(dec: &mut Decoder<R>, header: &BrushHeader)
+                                         -> Result<Brush, BrushError> {
+    Ok(Brush::Image(util::read_image(&mut dec.rdr, header)?))
+}
+
 struct BrushHeadResult {
+    end_pos: u64,
     next_brush_pos: u64,
 }
 
-/// Moves `dec` into position to read out the next brush with `do_brush_body`.
-/// Returns where the brush after this one is located.
-fn do_brush_head<R: Read + Seek>(dec: &mut Decoder<R>)
-                                   -> Result<BrushHeadResult, io::Error> {
-    let brush_pos = dec.next_brush_pos;
+/// Moves `dec` into position to read out the brush at `brush_pos` with
+/// `do_brush_body`. Returns where this brush ends and where the brush after
+/// it is located.
+fn do_brush_head<R: Read + Seek>(dec: &mut Decoder<R>, brush_pos: u64)
+                                 -> Result<BrushHeadResult, io::Error> {
     dec.rdr.seek(SeekFrom::Start(brush_pos))?;
 
     let len = dec.rdr.read_u32::<BigEndian>()? as u64;
@@ -85,14 +261,79 @@ fn do_brush_head<R: Read + Seek>(dec: &mut Decoder<R>)
     // Brushes are aligned to 4-byte boundaries; round up to get to one.
     let next_brush_pos = (end_pos + 3) & !3;
 
-    Ok(BrushHeadResult { next_brush_pos })
+    Ok(BrushHeadResult { end_pos, next_brush_pos })
+}
+
+/// What the header of a sampled brush says.
+struct ImageHeader {
+    uuid: String,
+    width: u32,
+    height: u32,
+    depth: u16,
+    compressed: bool,
+}
+
+/// With `dec` positioned by `do_brush_head`, reads out a brush that ends at
+/// `end_pos`.
+fn do_brush_body<R: Read + Seek>(dec: &mut Decoder<R>, end_pos: u64)
+                                 -> Result<ImageBrush, BrushError> {
+    let header = do_image_header(dec, end_pos)?;
+    let size = image_size(&header);
+
+    let data = if header.compressed {
+        util::read_rle_data(&mut dec.rdr, header.height, size)?
+    } else {
+        check_raw_size(dec, &header, end_pos)?;
+        let mut v = vec![0; size];
+        dec.rdr.read_exact(&mut v)?;
+        v
+    };
+
+    Ok(ImageBrush {
+        width: header.width,
+        height: header.height,
+        depth: header.depth,
+        data,
+        uuid: Some(header.uuid),
+        name: None,
+        spacing: None,
+        antialias: None,
+        compressed: header.compressed,
+    })
 }
 
-/// With `dec` positioned by `do_brush_head`, reads out a brush.
-fn do_brush_body<R: Read + Seek>(dec: &mut Decoder<R>) -> Result<ImageBrush, BrushError> {
-    // Skip over... something.
-    let skip_amt = if dec.subversion == 1 { 47 } else { 301 };
-    dec.rdr.seek(SeekFrom::Current(skip_amt))?;
+/// With `dec` positioned by `do_brush_head`, reads the header of a brush
+/// that ends at `end_pos`, leaving `dec` at the start of its samples.
+fn do_image_header<R: Read + Seek>(dec: &mut Decoder<R>, end_pos: u64)
+                                   -> Result<ImageHeader, BrushError> {
+    // The UUID that presets in the desc section use to refer to this brush.
+    let uuid = util::read_pascal_string(&mut dec.rdr)?;
+
+    let layout = match dec.layout {
+        Layout::Guess => guess_layout(&mut dec.rdr, end_pos)?,
+        layout => layout,
+    };
+
+    if let Layout::Short = layout {
+        // The bounds again, as u16s, and an unknown u16. We use the u32
+        // bounds below instead.
+        let _short_bounds = [
+            dec.rdr.read_u16::<BigEndian>()?,
+            dec.rdr.read_u16::<BigEndian>()?,
+            dec.rdr.read_u16::<BigEndian>()?,
+            dec.rdr.read_u16::<BigEndian>()?,
+        ];
+        let _unknown = dec.rdr.read_u16::<BigEndian>()?;
+    } else {
+        // Read rather than skipped over, so a file that ends in here is
+        // caught now.
+        let mut unknown = [0; LONG_EXTRA];
+        dec.rdr.read_exact(&mut unknown)?;
+    }
+
+    if util::tell(&mut dec.rdr)? > end_pos {
+        return Err(BrushError::MalformedHeader("header overruns brush"));
+    }
 
     let top = dec.rdr.read_u32::<BigEndian>()?;
     let left = dec.rdr.read_u32::<BigEndian>()?;
@@ -100,23 +341,71 @@ fn do_brush_body<R: Read + Seek>(dec: &mut Decoder<R>) -> Result<ImageBrush, Bru
     let right = dec.rdr.read_u32::<BigEndian>()?;
 
     let depth = dec.rdr.read_u16::<BigEndian>()?;
-    if depth != 8 {
+    if depth != 8 && depth != 16 {
         return Err(BrushError::UnsupportedBitDepth { depth });
     }
 
     let compressed = dec.rdr.read_u8()? != 0;
 
-    let width = right - left;
-    let height = bottom - top;
-    let size = (width as usize) * (height as usize) * (depth as usize >> 3);
+    if bottom < top || right < left {
+        return Err(BrushError::MalformedHeader("bad bounds"));
+    }
+
+    Ok(ImageHeader { uuid, width: right - left, height: bottom - top, depth, compressed })
+}
+
+/// Size of the brush's uncompressed samples.
+fn image_size(header: &ImageHeader) -> usize {
+    (header.width as usize) * (header.height as usize) * (header.depth as usize >> 3)
+}
+
+/// With `dec` positioned at the start of a brush's uncompressed samples,
+/// checks they fit before `end_pos`.
+fn check_raw_size<R: Read + Seek>(dec: &mut Decoder<R>, header: &ImageHeader, end_pos: u64)
+                                  -> Result<(), BrushError> {
+    if util::tell(&mut dec.rdr)? + image_size(header) as u64 > end_pos {
+        return Err(BrushError::MalformedHeader("image data overruns brush"));
+    }
+    Ok(())
+}
+
+/// Works out the layout of a sampled brush header by checking which one has
+/// a sensible-looking image header after it. `rdr` should be positioned just
+/// after the UUID; it is left there.
+fn guess_layout<R: Read + Seek>(rdr: &mut R, end_pos: u64) -> Result<Layout, BrushError> {
+    let pos = util::tell(rdr)?;
+    for &(layout, extra) in &[(Layout::Short, SHORT_EXTRA), (Layout::Long, LONG_EXTRA)] {
+        rdr.seek(SeekFrom::Start(pos + extra as u64))?;
+        let plausible = image_header_is_plausible(rdr, end_pos).unwrap_or(false);
+        if plausible {
+            rdr.seek(SeekFrom::Start(pos))?;
+            return Ok(layout);
+        }
+    }
+    Err(BrushError::MalformedHeader("couldn't guess header layout"))
+}
+
+/// Whether the bounds/depth/compression header at the current position
+/// looks like it describes an image that fits before `end_pos`.
+fn image_header_is_plausible<R: Read + Seek>(rdr: &mut R, end_pos: u64) -> io::Result<bool> {
+    let top = rdr.read_u32::<BigEndian>()?;
+    let left = rdr.read_u32::<BigEndian>()?;
+    let bottom = rdr.read_u32::<BigEndian>()?;
+    let right = rdr.read_u32::<BigEndian>()?;
+    let depth = rdr.read_u16::<BigEndian>()?;
+    let compression = rdr.read_u8()?;
+
+    if bottom < top || right < left || (depth != 8 && depth != 16) || compression > 1 {
+        return Ok(false);
+    }
 
-    let data = if compressed {
-        util::read_rle_data(&mut dec.rdr, height, size)?
+    let height = (bottom - top) as u64;
+    let width = (right - left) as u64;
+    // For RLE we can only check the table of row lengths fits.
+    let min_size = if compression == 1 {
+        2 * height
     } else {
-        let mut v = vec![0; size];
-        dec.rdr.read_exact(&mut v)?;
-        v
+        width * height * (depth as u64 >> 3)
     };
-
-    Ok(ImageBrush { width, height, depth, data })
+    Ok(util::tell(rdr)? + min_size <= end_pos)
 }
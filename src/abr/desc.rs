@@ -0,0 +1,442 @@
+//! Parser and writer for Photoshop action descriptors, the structured
+//! key/value format used in the `desc` section of ABR6 files.
+
+use std::io::{self, Read, Write};
+use super::byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use super::DescriptorError;
+use super::util::{read_unicode_string, write_unicode_string};
+
+/// How deeply descriptors/lists may nest before we assume the data is bad.
+const MAX_DEPTH: u32 = 64;
+
+/// An action descriptor: a class and a list of keyed values.
+#[derive(Debug, Clone)]
+pub struct Descriptor {
+    /// Human-readable class name (often empty).
+    pub name: String,
+    /// Class ID, eg. `"brushPreset"` or `"null"`.
+    pub class_id: String,
+    /// The descriptor's items, in file order.
+    pub items: Vec<(String, Value)>,
+}
+
+/// A value in an action descriptor.
+#[derive(Debug, Clone)]
+pub enum Value {
+    /// `Objc`/`GlbO`: a nested descriptor.
+    Descriptor(Descriptor),
+    /// `VlLs`: a list of values.
+    List(Vec<Value>),
+    /// `doub`: a plain float.
+    Double(f64),
+    /// `UntF`: a float with a unit, eg. `"#Pxl"`, `"#Prc"`, `"#Ang"`.
+    UnitFloat { unit: String, value: f64 },
+    /// `UnFl`: a list of floats sharing one unit.
+    UnitFloats { unit: String, values: Vec<f64> },
+    /// `TEXT`: a string.
+    Text(String),
+    /// `enum`: an enumerated value.
+    Enum { ty: String, value: String },
+    /// `long`: a 32-bit integer.
+    Integer(i32),
+    /// `comp`: a 64-bit integer.
+    LargeInteger(i64),
+    /// `bool`: a boolean.
+    Bool(bool),
+    /// `type`/`GlbC`: a class.
+    Class { name: String, class_id: String },
+    /// `obj `: a reference.
+    Reference(Vec<ReferenceItem>),
+    /// `alis`: alias data (opaque).
+    Alias(Vec<u8>),
+    /// `tdta`: raw data.
+    RawData(Vec<u8>),
+}
+
+/// One item of a reference (`obj `) value.
+#[derive(Debug, Clone)]
+pub enum ReferenceItem {
+    /// `prop`: a property of a class.
+    Property { name: String, class_id: String, key: String },
+    /// `Clss`: a class.
+    Class { name: String, class_id: String },
+    /// `Enmr`: an enumerated reference.
+    Enum { name: String, class_id: String, ty: String, value: String },
+    /// `rele`: an offset.
+    Offset { name: String, class_id: String, offset: u32 },
+    /// `Idnt`: an identifier.
+    Identifier(u32),
+    /// `indx`: an index.
+    Index(u32),
+    /// `name`: a named object.
+    Name { name: String, class_id: String, value: String },
+}
+
+impl Descriptor {
+    /// Gets the value of the first item with key `key`.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.items.iter()
+            .find(|&(k, _)| k == key)
+            .map(|(_, v)| v)
+    }
+}
+
+impl Value {
+    /// The value as a float, if it is a `doub`, `UntF`, `long` or `comp`.
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Value::Double(x) => Some(x),
+            Value::UnitFloat { value, .. } => Some(value),
+            Value::Integer(x) => Some(x as f64),
+            Value::LargeInteger(x) => Some(x as f64),
+            _ => None,
+        }
+    }
+
+    /// The value as a string, if it is `TEXT`.
+    pub fn as_str(&self) -> Option<&str> {
+        match *self {
+            Value::Text(ref s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// The value as a bool, if it is a `bool`.
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self {
+            Value::Bool(b) => Some(b),
+            _ => None,
+        }
+    }
+
+    /// The value as a descriptor, if it is an `Objc`/`GlbO`.
+    pub fn as_descriptor(&self) -> Option<&Descriptor> {
+        match *self {
+            Value::Descriptor(ref d) => Some(d),
+            _ => None,
+        }
+    }
+
+    /// The value as a list, if it is a `VlLs`.
+    pub fn as_list(&self) -> Option<&[Value]> {
+        match *self {
+            Value::List(ref l) => Some(l),
+            _ => None,
+        }
+    }
+}
+
+/// Reads a descriptor (not including any leading version number).
+pub fn read_descriptor<R: Read>(rdr: &mut R) -> Result<Descriptor, DescriptorError> {
+    read_descriptor_at(rdr, 0)
+}
+
+fn read_descriptor_at<R: Read>(rdr: &mut R, depth: u32) -> Result<Descriptor, DescriptorError> {
+    if depth > MAX_DEPTH {
+        return Err(DescriptorError::TooDeep);
+    }
+
+    let name = read_unicode_string(rdr)?;
+    let class_id = read_id(rdr)?;
+    let count = rdr.read_u32::<BigEndian>()?;
+
+    let mut items = vec![];
+    for _ in 0..count {
+        let key = read_id(rdr)?;
+        let value = read_value(rdr, depth)?;
+        items.push((key, value));
+    }
+
+    Ok(Descriptor { name, class_id, items })
+}
+
+/// Reads an OSType tag followed by a value of that type.
+fn read_value<R: Read>(rdr: &mut R, depth: u32) -> Result<Value, DescriptorError> {
+    let ty = read_ostype(rdr)?;
+    Ok(match &ty[..] {
+        b"Objc" | b"GlbO" => Value::Descriptor(read_descriptor_at(rdr, depth + 1)?),
+        b"VlLs" => {
+            let count = rdr.read_u32::<BigEndian>()?;
+            let mut values = vec![];
+            for _ in 0..count {
+                values.push(read_value(rdr, depth + 1)?);
+            }
+            Value::List(values)
+        }
+        b"doub" => Value::Double(rdr.read_f64::<BigEndian>()?),
+        b"UntF" => {
+            let unit = ostype_to_string(read_ostype(rdr)?);
+            let value = rdr.read_f64::<BigEndian>()?;
+            Value::UnitFloat { unit, value }
+        }
+        b"UnFl" => {
+            let unit = ostype_to_string(read_ostype(rdr)?);
+            let count = rdr.read_u32::<BigEndian>()?;
+            let mut values = vec![];
+            for _ in 0..count {
+                values.push(rdr.read_f64::<BigEndian>()?);
+            }
+            Value::UnitFloats { unit, values }
+        }
+        b"TEXT" => Value::Text(read_unicode_string(rdr)?),
+        b"enum" => {
+            let ty = read_id(rdr)?;
+            let value = read_id(rdr)?;
+            Value::Enum { ty, value }
+        }
+        b"long" => Value::Integer(rdr.read_i32::<BigEndian>()?),
+        b"comp" => Value::LargeInteger(rdr.read_i64::<BigEndian>()?),
+        b"bool" => Value::Bool(rdr.read_u8()? != 0),
+        b"type" | b"GlbC" => {
+            let name = read_unicode_string(rdr)?;
+            let class_id = read_id(rdr)?;
+            Value::Class { name, class_id }
+        }
+        b"obj " => Value::Reference(read_reference(rdr)?),
+        b"alis" => Value::Alias(read_data(rdr)?),
+        b"tdta" => Value::RawData(read_data(rdr)?),
+        _ => return Err(DescriptorError::UnknownType { ty: ostype_to_string(ty) }),
+    })
+}
+
+fn read_reference<R: Read>(rdr: &mut R) -> Result<Vec<ReferenceItem>, DescriptorError> {
+    let count = rdr.read_u32::<BigEndian>()?;
+    let mut items = vec![];
+    for _ in 0..count {
+        let ty = read_ostype(rdr)?;
+        items.push(match &ty[..] {
+            b"prop" => {
+                let name = read_unicode_string(rdr)?;
+                let class_id = read_id(rdr)?;
+                let key = read_id(rdr)?;
+                ReferenceItem::Property { name, class_id, key }
+            }
+            b"Clss" => {
+                let name = read_unicode_string(rdr)?;
+                let class_id = read_id(rdr)?;
+                ReferenceItem::Class { name, class_id }
+            }
+            b"Enmr" => {
+                let name = read_unicode_string(rdr)?;
+                let class_id = read_id(rdr)?;
+                let ty = read_id(rdr)?;
+                let value = read_id(rdr)?;
+                ReferenceItem::Enum { name, class_id, ty, value }
+            }
+            b"rele" => {
+                let name = read_unicode_string(rdr)?;
+                let class_id = read_id(rdr)?;
+                let offset = rdr.read_u32::<BigEndian>()?;
+                ReferenceItem::Offset { name, class_id, offset }
+            }
+            b"Idnt" => ReferenceItem::Identifier(rdr.read_u32::<BigEndian>()?),
+            b"indx" => ReferenceItem::Index(rdr.read_u32::<BigEndian>()?),
+            b"name" => {
+                let name = read_unicode_string(rdr)?;
+                let class_id = read_id(rdr)?;
+                let value = read_unicode_string(rdr)?;
+                ReferenceItem::Name { name, class_id, value }
+            }
+            _ => return Err(DescriptorError::UnknownType { ty: ostype_to_string(ty) }),
+        });
+    }
+    Ok(items)
+}
+
+fn read_ostype<R: Read>(rdr: &mut R) -> Result<[u8; 4], DescriptorError> {
+    let mut buf = [0; 4];
+    rdr.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn ostype_to_string(ty: [u8; 4]) -> String {
+    String::from_utf8_lossy(&ty[..]).into_owned()
+}
+
+/// Reads a key/class ID. These are either a 4-byte OSType (when the length
+/// prefix is zero) or a length-prefixed ASCII string.
+fn read_id<R: Read>(rdr: &mut R) -> Result<String, DescriptorError> {
+    let len = rdr.read_u32::<BigEndian>()?;
+    if len == 0 {
+        Ok(ostype_to_string(read_ostype(rdr)?))
+    } else {
+        let bytes = read_exact_vec(rdr, len as u64)?;
+        Ok(String::from_utf8_lossy(&bytes).into_owned())
+    }
+}
+
+/// Reads length-prefixed raw data.
+fn read_data<R: Read>(rdr: &mut R) -> Result<Vec<u8>, DescriptorError> {
+    let len = rdr.read_u32::<BigEndian>()?;
+    read_exact_vec(rdr, len as u64)
+}
+
+/// Reads exactly `len` bytes. Doesn't trust `len` enough to allocate it all
+/// up-front.
+fn read_exact_vec<R: Read>(rdr: &mut R, len: u64) -> Result<Vec<u8>, DescriptorError> {
+    let mut v = vec![];
+    rdr.by_ref().take(len).read_to_end(&mut v)?;
+    if (v.len() as u64) < len {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated data").into());
+    }
+    Ok(v)
+}
+
+/// Writes a descriptor (not including any leading version number), in the
+/// form `read_descriptor` reads.
+pub fn write_descriptor<W: Write>(w: &mut W, desc: &Descriptor) -> io::Result<()> {
+    write_unicode_string(w, &desc.name)?;
+    write_id(w, &desc.class_id)?;
+    w.write_u32::<BigEndian>(desc.items.len() as u32)?;
+    for (key, value) in &desc.items {
+        write_id(w, key)?;
+        write_value(w, value)?;
+    }
+    Ok(())
+}
+
+/// Writes a value preceded by its OSType tag.
+fn write_value<W: Write>(w: &mut W, value: &Value) -> io::Result<()> {
+    match *value {
+        Value::Descriptor(ref desc) => {
+            w.write_all(b"Objc")?;
+            write_descriptor(w, desc)?;
+        }
+        Value::List(ref values) => {
+            w.write_all(b"VlLs")?;
+            w.write_u32::<BigEndian>(values.len() as u32)?;
+            for value in values {
+                write_value(w, value)?;
+            }
+        }
+        Value::Double(x) => {
+            w.write_all(b"doub")?;
+            w.write_f64::<BigEndian>(x)?;
+        }
+        Value::UnitFloat { ref unit, value } => {
+            w.write_all(b"UntF")?;
+            write_ostype(w, unit)?;
+            w.write_f64::<BigEndian>(value)?;
+        }
+        Value::UnitFloats { ref unit, ref values } => {
+            w.write_all(b"UnFl")?;
+            write_ostype(w, unit)?;
+            w.write_u32::<BigEndian>(values.len() as u32)?;
+            for &x in values {
+                w.write_f64::<BigEndian>(x)?;
+            }
+        }
+        Value::Text(ref s) => {
+            w.write_all(b"TEXT")?;
+            write_unicode_string(w, s)?;
+        }
+        Value::Enum { ref ty, ref value } => {
+            w.write_all(b"enum")?;
+            write_id(w, ty)?;
+            write_id(w, value)?;
+        }
+        Value::Integer(x) => {
+            w.write_all(b"long")?;
+            w.write_i32::<BigEndian>(x)?;
+        }
+        Value::LargeInteger(x) => {
+            w.write_all(b"comp")?;
+            w.write_i64::<BigEndian>(x)?;
+        }
+        Value::Bool(b) => {
+            w.write_all(b"bool")?;
+            w.write_u8(b as u8)?;
+        }
+        Value::Class { ref name, ref class_id } => {
+            w.write_all(b"type")?;
+            write_unicode_string(w, name)?;
+            write_id(w, class_id)?;
+        }
+        Value::Reference(ref items) => {
+            w.write_all(b"obj ")?;
+            write_reference(w, items)?;
+        }
+        Value::Alias(ref data) => {
+            w.write_all(b"alis")?;
+            write_data(w, data)?;
+        }
+        Value::RawData(ref data) => {
+            w.write_all(b"tdta")?;
+            write_data(w, data)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_reference<W: Write>(w: &mut W, items: &[ReferenceItem]) -> io::Result<()> {
+    w.write_u32::<BigEndian>(items.len() as u32)?;
+    for item in items {
+        match *item {
+            ReferenceItem::Property { ref name, ref class_id, ref key } => {
+                w.write_all(b"prop")?;
+                write_unicode_string(w, name)?;
+                write_id(w, class_id)?;
+                write_id(w, key)?;
+            }
+            ReferenceItem::Class { ref name, ref class_id } => {
+                w.write_all(b"Clss")?;
+                write_unicode_string(w, name)?;
+                write_id(w, class_id)?;
+            }
+            ReferenceItem::Enum { ref name, ref class_id, ref ty, ref value } => {
+                w.write_all(b"Enmr")?;
+                write_unicode_string(w, name)?;
+                write_id(w, class_id)?;
+                write_id(w, ty)?;
+                write_id(w, value)?;
+            }
+            ReferenceItem::Offset { ref name, ref class_id, offset } => {
+                w.write_all(b"rele")?;
+                write_unicode_string(w, name)?;
+                write_id(w, class_id)?;
+                w.write_u32::<BigEndian>(offset)?;
+            }
+            ReferenceItem::Identifier(x) => {
+                w.write_all(b"Idnt")?;
+                w.write_u32::<BigEndian>(x)?;
+            }
+            ReferenceItem::Index(x) => {
+                w.write_all(b"indx")?;
+                w.write_u32::<BigEndian>(x)?;
+            }
+            ReferenceItem::Name { ref name, ref class_id, ref value } => {
+                w.write_all(b"name")?;
+                write_unicode_string(w, name)?;
+                write_id(w, class_id)?;
+                write_unicode_string(w, value)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Writes an OSType, padding or truncating `ty` to four bytes.
+fn write_ostype<W: Write>(w: &mut W, ty: &str) -> io::Result<()> {
+    let mut buf = *b"    ";
+    for (b, &c) in buf.iter_mut().zip(ty.as_bytes()) {
+        *b = c;
+    }
+    w.write_all(&buf)
+}
+
+/// Writes a key/class ID. Four-character IDs are written as an OSType, like
+/// Photoshop does; anything else is written as a length-prefixed string.
+fn write_id<W: Write>(w: &mut W, id: &str) -> io::Result<()> {
+    if id.len() == 4 {
+        w.write_u32::<BigEndian>(0)?;
+    } else {
+        w.write_u32::<BigEndian>(id.len() as u32)?;
+    }
+    w.write_all(id.as_bytes())
+}
+
+/// Writes length-prefixed raw data.
+fn write_data<W: Write>(w: &mut W, data: &[u8]) -> io::Result<()> {
+    w.write_u32::<BigEndian>(data.len() as u32)?;
+    w.write_all(data)
+}
@@ -1,8 +1,10 @@
 use std::io;
 
 quick_error! {
+    /// Error from opening an ABR file.
     #[derive(Debug)]
     pub enum OpenError {
+        /// The header names a version we don't know how to read.
         UnsupportedVersion {
             version: u16,
             subversion: u16,
@@ -10,10 +12,12 @@ quick_error! {
             description("unknown/unsupported version")
             display("unknown/unsupported version: {}.{}", version, subversion)
         }
-        Found8bim {
-            // What IS this?
-            description("found 8bim")
+        /// Found a block with a signature other than `8BIM`/`8B64`.
+        BadBlockSignature { signature: String } {
+            description("bad block signature")
+            display("bad block signature: {:?}", signature)
         }
+        /// Reading from the underlying stream failed.
         IoError(err: io::Error) {
             description("read error")
             display("read error: {}", err)
@@ -24,16 +28,77 @@ quick_error! {
 }
 
 quick_error! {
+    /// Error from reading out a single brush.
     #[derive(Debug)]
     pub enum BrushError {
+        /// The brush's samples have a bit-depth we can't decode.
         UnsupportedBitDepth { depth: u16 } {
             description("unsupported bit-depth")
             display("unsupported bit-depth, {}-bit", depth)
         }
+        /// The brush's header doesn't make sense.
+        MalformedHeader(reason: &'static str) {
+            description("malformed brush header")
+            display("malformed brush header: {}", reason)
+        }
+        /// The brush is of a type we don't know how to read.
         UnsupportedBrushType { ty: u16 } {
             description("unsupported brush type")
             display("unsupported brush type: {}", ty)
         }
+        /// Reading from the underlying stream failed.
+        IoError(err: io::Error) {
+            description("read error")
+            display("read error: {}", err)
+            cause(err)
+            from()
+        }
+    }
+}
+
+quick_error! {
+    /// Error from reading the action descriptor in an ABR's `desc` section.
+    #[derive(Debug)]
+    pub enum DescriptorError {
+        /// Found a value or reference type we don't know how to read.
+        UnknownType { ty: String } {
+            description("unknown descriptor type")
+            display("unknown descriptor type: {:?}", ty)
+        }
+        /// The descriptor is nested too deeply to be real.
+        TooDeep {
+            description("descriptor nested too deeply")
+        }
+        /// Reading from the underlying stream failed.
+        IoError(err: io::Error) {
+            description("read error")
+            display("read error: {}", err)
+            cause(err)
+            from()
+        }
+    }
+}
+
+quick_error! {
+    /// Error from reading a pattern out of an ABR's `patt` section.
+    #[derive(Debug)]
+    pub enum PatternError {
+        /// The pattern's image mode isn't greyscale, indexed, or RGB.
+        UnsupportedMode { mode: u32 } {
+            description("unsupported pattern image mode")
+            display("unsupported pattern image mode: {}", mode)
+        }
+        /// The pattern's samples have a bit-depth we can't decode.
+        UnsupportedBitDepth { depth: u16 } {
+            description("unsupported bit-depth")
+            display("unsupported bit-depth, {}-bit", depth)
+        }
+        /// The pattern's header doesn't make sense.
+        MalformedHeader(reason: &'static str) {
+            description("malformed pattern header")
+            display("malformed pattern header: {}", reason)
+        }
+        /// Reading from the underlying stream failed.
         IoError(err: io::Error) {
             description("read error")
             display("read error: {}", err)
@@ -1,18 +1,62 @@
+//! Decoder and writer for Adobe Photoshop brush (ABR) files.
+//!
+//! Call [`open`](fn.open.html) on a seekable reader (or
+//! [`open_slice`](fn.open_slice.html) on a file in memory) to get an
+//! iterator over the brushes the file contains, or
+//! [`AbrFile::open`](struct.AbrFile.html#method.open) to read them in any
+//! order. [`write`](fn.write.html) goes the other way, writing image brushes
+//! out as an ABR file.
+//!
+//! **Breaking change:** the iterator used to yield
+//! [`ImageBrush`](struct.ImageBrush.html)es and now yields
+//! [`Brush`](enum.Brush.html)es, since ABR1/ABR2 files can also hold
+//! computed brushes. [`Brush::into_image`](enum.Brush.html#method.into_image)
+//! gets an `ImageBrush` as before, rasterising computed brushes.
+
 extern crate byteorder;
 mod abr1;
 mod abr6;
+mod computed;
+mod desc;
 mod err;
+mod pattern;
+mod preset;
 mod util;
+mod writer;
 
-pub use self::err::{OpenError, BrushError};
+pub use self::computed::ComputedBrush;
+pub use self::desc::{Descriptor, Value, ReferenceItem};
+pub use self::err::{OpenError, BrushError, DescriptorError, PatternError};
+pub use self::pattern::{Pattern, PatternColor};
+pub use self::preset::BrushPreset;
+pub use self::writer::{save, write, WriteOptions};
 use self::byteorder::{BigEndian, ReadBytesExt};
-use std::io::{Read, Seek};
+use std::io::{Cursor, Read, Seek};
 
 enum Decoder<R> {
     Abr1(abr1::Decoder<R>),
     Abr6(abr6::Decoder<R>),
 }
 
+/// A brush read out of an ABR file.
+#[derive(Debug)]
+pub enum Brush {
+    /// A sampled brush, with a tip image.
+    Image(ImageBrush),
+    /// A computed brush, described by its parameters (ABR1/ABR2 only).
+    Computed(ComputedBrush),
+}
+
+impl Brush {
+    /// Gets the brush's tip image, rasterising it if it is computed.
+    pub fn into_image(self) -> ImageBrush {
+        match self {
+            Brush::Image(brush) => brush,
+            Brush::Computed(brush) => brush.rasterize(),
+        }
+    }
+}
+
 /// An image brush.
 #[derive(Debug)]
 pub struct ImageBrush {
@@ -20,41 +64,359 @@ pub struct ImageBrush {
     pub width: u32,
     /// Image height.
     pub height: u32,
-    /// Bit-depth (always 8, currently).
+    /// Bit-depth (8 or 16).
     pub depth: u16,
-    /// Row-major vector of width×height image samples.
+    /// Row-major vector of width×height image samples. 16-bit samples are
+    /// stored big-endian, as in the ABR file (and as PNG expects).
     pub data: Vec<u8>,
+    /// The brush's UUID (ABR6 only). Brush presets refer to the brush by
+    /// this in their `sampled_data`.
+    pub uuid: Option<String>,
+    /// The brush's name (ABR2 only). ABR6 keeps names in the presets.
+    pub name: Option<String>,
+    /// Spacing, as a percentage of the brush size (ABR1/ABR2 only). ABR6
+    /// keeps spacing in the presets.
+    pub spacing: Option<u16>,
+    /// Whether the brush is painted anti-aliased (ABR1/ABR2 only). ABR6
+    /// keeps this in the presets.
+    pub antialias: Option<bool>,
+    /// Whether the samples were run-length compressed in the file.
+    pub compressed: bool,
+}
+
+impl ImageBrush {
+    /// Converts a 16-bit brush to 8-bit, rounding each sample to the nearest
+    /// 8-bit value. 8-bit brushes are returned unchanged.
+    pub fn into_8bit(self) -> ImageBrush {
+        if self.depth != 16 {
+            return self;
+        }
+        let data = self.data.chunks_exact(2)
+            .map(|c| {
+                let x = ((c[0] as u32) << 8) | c[1] as u32;
+                ((x * 255 + 32767) / 65535) as u8
+            })
+            .collect();
+        ImageBrush { depth: 8, data, ..self }
+    }
+}
+
+/// An iterator over an ABR's brushes.
+///
+/// A brush that fails to decode is yielded as an `Err`; iteration generally
+/// continues with the next brush afterwards.
+pub struct Brushes<R> {
+    dec: Decoder<R>,
+    version: u16,
+    subversion: Option<u16>,
+}
+
+/// One of the 8BIM/8B64 blocks an ABR6 file is made of.
+#[derive(Debug, Clone)]
+pub struct Block {
+    /// The block's signature, eg. `"8BIM"`.
+    pub signature: String,
+    /// What the block holds, eg. `"samp"`, `"desc"` or `"patt"`.
+    pub key: String,
+    /// Offset of the block's data in the file.
+    pub offset: u64,
+    /// Length of the block's data.
+    pub len: u64,
+}
+
+/// Where a brush is in an ABR file.
+#[derive(Debug, Clone, Copy)]
+pub struct BrushRecord {
+    /// Offset of the brush in the file.
+    pub offset: u64,
+    /// Length of the brush, including its length field.
+    pub len: u64,
+}
+
+/// What a brush's header says about it, read without decoding its samples.
+#[derive(Debug, Clone)]
+pub struct BrushHeader {
+    /// Where the brush is in the file.
+    pub record: BrushRecord,
+    /// Whether it's a computed brush (ABR1/ABR2 only). Computed brushes
+    /// have no samples; their width and height are the diameter.
+    pub computed: bool,
+    /// Image width.
+    pub width: u32,
+    /// Image height.
+    pub height: u32,
+    /// Bit-depth (8 or 16). Computed brushes don't have one.
+    pub depth: Option<u16>,
+    /// Whether the samples are run-length compressed.
+    pub compressed: bool,
+    /// Length of the compressed samples, from the table of row lengths.
+    pub compressed_len: Option<u64>,
+    /// Offset of the samples in the file (of the table of row lengths, if
+    /// they're compressed). Computed brushes don't have any.
+    pub samples_offset: Option<u64>,
+    /// The brush's UUID (ABR6 only).
+    pub uuid: Option<String>,
+    /// The brush's name (ABR2 only).
+    pub name: Option<String>,
+    /// Spacing, as a percentage of the brush size (ABR1/ABR2 only).
+    pub spacing: Option<u16>,
+    /// Whether the brush is anti-aliased (ABR1/ABR2 image brushes only).
+    pub antialias: Option<bool>,
 }
 
-/// An iterator over an ABR's image brushes.
-pub struct Brushes<R>(Decoder<R>);
+/// An ABR file, with its brushes indexed so any one of them can be read
+/// without reading the ones before it.
+pub struct AbrFile<R> {
+    brushes: Brushes<R>,
+    records: Vec<BrushRecord>,
+    /// The header of each brush in `records`.
+    headers: Vec<Result<BrushHeader, BrushError>>,
+}
+
+/// Options for opening an ABR file.
+#[derive(Debug, Clone, Default)]
+pub struct OpenOptions {
+    /// Read files with an unknown version or subversion as ABR6 anyway,
+    /// guessing how each brush's header is laid out.
+    pub guess_unknown_versions: bool,
+}
 
-/// Gets an iterator over the image brushes in an ABR file in `rdr`.
-pub fn open<R: Read + Seek>(mut rdr: R) -> Result<Brushes<R>, OpenError> {
+/// Gets an iterator over the brushes in an ABR file in `rdr`.
+pub fn open<R: Read + Seek>(rdr: R) -> Result<Brushes<R>, OpenError> {
+    open_with_options(rdr, &OpenOptions::default())
+}
+
+/// Gets an iterator over the brushes in an ABR file held in memory, eg. one
+/// that's been memory-mapped. This reads through a `Cursor` over `data`, so
+/// seeking from brush to brush costs nothing, but samples are still copied
+/// out of `data` as they're decoded.
+pub fn open_slice(data: &[u8]) -> Result<Brushes<Cursor<&[u8]>>, OpenError> {
+    open_slice_with_options(data, &OpenOptions::default())
+}
+
+/// Gets an iterator over the brushes in an ABR file held in memory, using
+/// `options`. See `open_slice`.
+pub fn open_slice_with_options<'a>(data: &'a [u8], options: &OpenOptions)
+                                   -> Result<Brushes<Cursor<&'a [u8]>>, OpenError> {
+    open_with_options(Cursor::new(data), options)
+}
+
+/// Gets an iterator over the brushes in an ABR file in `rdr`, using
+/// `options`.
+pub fn open_with_options<R: Read + Seek>(mut rdr: R, options: &OpenOptions)
+                                         -> Result<Brushes<R>, OpenError> {
     let version = rdr.read_u16::<BigEndian>()?;
     let subversion = rdr.read_u16::<BigEndian>()?;
 
     let abr1_like =
         version == 1 || version == 2;
+    // Versions 7 and 9 use the same layout as 6 and 10.
     let abr6_like =
-        (version == 6 || version == 10) && (subversion == 1 || subversion == 2);
+        version == 6 || version == 7 || version == 9 || version == 10;
+    let abr6_layout = match subversion {
+        1 => Some(abr6::Layout::Short),
+        2 => Some(abr6::Layout::Long),
+        _ => None,
+    };
+
+    let dec = if abr1_like {
+        // What would be the subversion is the brush count.
+        Decoder::Abr1(abr1::open(rdr, version, subversion)?)
+    } else if let (true, Some(layout)) = (abr6_like, abr6_layout) {
+        Decoder::Abr6(abr6::open(rdr, version, layout)?)
+    } else if options.guess_unknown_versions {
+        Decoder::Abr6(abr6::open(rdr, version, abr6::Layout::Guess)?)
+    } else {
+        return Err(OpenError::UnsupportedVersion { version, subversion });
+    };
+    let subversion = if abr1_like { None } else { Some(subversion) };
+
+    Ok(Brushes { dec, version, subversion })
+}
+
+impl<R: Read + Seek> Brushes<R> {
+    /// The file's version.
+    pub fn version(&self) -> u16 {
+        self.version
+    }
+
+    /// The file's subversion. ABR1/ABR2 files don't have one.
+    pub fn subversion(&self) -> Option<u16> {
+        self.subversion
+    }
+
+    /// The blocks the file is made of, in file order. Only ABR6 files have
+    /// blocks.
+    pub fn blocks(&self) -> &[Block] {
+        match self.dec {
+            Decoder::Abr6(ref dec) => abr6::blocks(dec),
+            Decoder::Abr1(_) => &[],
+        }
+    }
+
+    /// Offset in the file of the brush that the next call to `next` will
+    /// read, or `None` if there are no more brushes.
+    pub fn next_offset(&self) -> Option<u64> {
+        match self.dec {
+            Decoder::Abr6(ref dec) => abr6::next_offset(dec),
+            Decoder::Abr1(ref dec) => abr1::next_offset(dec),
+        }
+    }
 
-    Ok(Brushes(
-        if abr1_like {
-            Decoder::Abr1(abr1::open(rdr, version, subversion)?)
-        } else if abr6_like {
-            Decoder::Abr6(abr6::open(rdr, version, subversion)?)
-        } else {
-            return Err(OpenError::UnsupportedVersion { version, subversion });
+    /// Reads the action descriptor in the file's `desc` section. Only ABR6
+    /// files have one; for other files, or if there isn't one, returns
+    /// `Ok(None)`.
+    pub fn descriptor(&mut self) -> Result<Option<Descriptor>, DescriptorError> {
+        match self.dec {
+            Decoder::Abr6(ref mut dec) => abr6::read_descriptor(dec),
+            Decoder::Abr1(_) => Ok(None),
         }
-    ))
+    }
+
+    /// Reads the brush presets from the file's `desc` section. Use the
+    /// presets' `sampled_data` to find the image brush each one uses.
+    ///
+    /// This can be called at any point during iteration.
+    pub fn presets(&mut self) -> Result<Vec<BrushPreset>, DescriptorError> {
+        Ok(match self.descriptor()? {
+            Some(desc) => preset::presets_from_descriptor(&desc),
+            None => vec![],
+        })
+    }
+
+    /// Reads the texture patterns from the file's `patt` section. Only ABR6
+    /// files have one. A pattern that fails to decode is returned as an
+    /// `Err`.
+    ///
+    /// This can be called at any point during iteration.
+    pub fn patterns(&mut self) -> Vec<Result<Pattern, PatternError>> {
+        match self.dec {
+            Decoder::Abr6(ref mut dec) => abr6::read_patterns(dec),
+            Decoder::Abr1(_) => vec![],
+        }
+    }
+
+    /// Counts the patterns in the file's `patt` section by walking their
+    /// lengths, without decoding them. This is `patterns().len()`, but much
+    /// quicker.
+    ///
+    /// This can be called at any point during iteration.
+    pub fn count_patterns(&mut self) -> usize {
+        match self.dec {
+            Decoder::Abr6(ref mut dec) => abr6::count_patterns(dec),
+            Decoder::Abr1(_) => 0,
+        }
+    }
+}
+
+impl<R: Read + Seek> AbrFile<R> {
+    /// Opens the ABR file in `rdr` and indexes its brushes.
+    pub fn open(rdr: R) -> Result<AbrFile<R>, OpenError> {
+        AbrFile::open_with_options(rdr, &OpenOptions::default())
+    }
+
+    /// Opens the ABR file in `rdr`, using `options`, and indexes its
+    /// brushes.
+    pub fn open_with_options(rdr: R, options: &OpenOptions) -> Result<AbrFile<R>, OpenError> {
+        Ok(AbrFile::from_brushes(open_with_options(rdr, options)?))
+    }
+
+    /// Indexes the brushes `brushes` has left to iterate over.
+    ///
+    /// Indexing reads each brush's header, but not its samples, so it's
+    /// quick even for big files. If a brush's length can't be read, the
+    /// brushes from it on are left out.
+    pub fn from_brushes(mut brushes: Brushes<R>) -> AbrFile<R> {
+        let (records, headers) = match brushes.dec {
+            Decoder::Abr6(ref mut dec) => {
+                let records = abr6::index(dec);
+                let headers = records.iter().map(|record| abr6::header_at(dec, record)).collect();
+                (records, headers)
+            }
+            Decoder::Abr1(ref mut dec) => {
+                let records = abr1::index(dec);
+                let headers = records.iter().map(|record| abr1::header_at(dec, record)).collect();
+                (records, headers)
+            }
+        };
+        AbrFile { brushes, records, headers }
+    }
+
+    /// The number of brushes in the file.
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    /// Whether the file has no brushes.
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Where each brush is in the file.
+    pub fn records(&self) -> &[BrushRecord] {
+        &self.records
+    }
+
+    /// Reads the brush at index `i`, or returns `None` if there isn't one.
+    /// Its header isn't read again.
+    pub fn brush(&mut self, i: usize) -> Option<Result<Brush, BrushError>> {
+        let record = *self.records.get(i)?;
+        let header = self.headers[i].as_ref().ok();
+        Some(match (&mut self.brushes.dec, header) {
+            (&mut Decoder::Abr6(ref mut dec), Some(header)) => abr6::brush_from_header(dec, header),
+            (&mut Decoder::Abr1(ref mut dec), Some(header)) => abr1::brush_from_header(dec, header),
+            // Read it again, for the error.
+            (&mut Decoder::Abr6(ref mut dec), None) => abr6::brush_at(dec, &record),
+            (&mut Decoder::Abr1(ref mut dec), None) => abr1::brush_at(dec, &record),
+        })
+    }
+
+    /// The header of the brush at index `i`, or `None` if there isn't one.
+    pub fn header(&self, i: usize) -> Option<Result<&BrushHeader, &BrushError>> {
+        self.headers.get(i).map(|header| header.as_ref())
+    }
+
+    /// The headers of all the brushes, as read when they were indexed. This
+    /// is much quicker than reading the brushes when only their sizes are
+    /// wanted.
+    pub fn scan(&self) -> &[Result<BrushHeader, BrushError>] {
+        &self.headers
+    }
+
+    /// The file's version.
+    pub fn version(&self) -> u16 {
+        self.brushes.version()
+    }
+
+    /// The file's subversion. ABR1/ABR2 files don't have one.
+    pub fn subversion(&self) -> Option<u16> {
+        self.brushes.subversion()
+    }
+
+    /// The blocks the file is made of, in file order. Only ABR6 files have
+    /// blocks.
+    pub fn blocks(&self) -> &[Block] {
+        self.brushes.blocks()
+    }
+
+    /// Reads the brush presets from the file's `desc` section. See
+    /// `Brushes::presets`.
+    pub fn presets(&mut self) -> Result<Vec<BrushPreset>, DescriptorError> {
+        self.brushes.presets()
+    }
+
+    /// Reads the texture patterns from the file's `patt` section. See
+    /// `Brushes::patterns`.
+    pub fn patterns(&mut self) -> Vec<Result<Pattern, PatternError>> {
+        self.brushes.patterns()
+    }
 }
 
 impl<R: Read + Seek> Iterator for Brushes<R> {
-    type Item = Result<ImageBrush, BrushError>;
+    type Item = Result<Brush, BrushError>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        match self.0 {
+        match self.dec {
             Decoder::Abr6(ref mut dec) => abr6::next_brush(dec),
             Decoder::Abr1(ref mut dec) => abr1::next_brush(dec),
         }
@@ -0,0 +1,333 @@
+use std::io::{self, Read, Seek, SeekFrom};
+use super::byteorder::{BigEndian, ReadBytesExt};
+use super::PatternError;
+use super::util;
+
+/// A texture pattern from an ABR6 file's `patt` section.
+#[derive(Debug)]
+pub struct Pattern {
+    /// Pattern name.
+    pub name: String,
+    /// The pattern's UUID. Brush presets with a texture refer to the
+    /// pattern by this.
+    pub uuid: String,
+    /// Image width.
+    pub width: u32,
+    /// Image height.
+    pub height: u32,
+    /// Bit-depth per channel (8 or 16).
+    pub depth: u16,
+    /// Channel layout of `data`.
+    pub color: PatternColor,
+    /// Row-major vector of width×height interleaved pixels. 16-bit samples
+    /// are stored big-endian. Indexed patterns are expanded to RGB.
+    pub data: Vec<u8>,
+}
+
+/// Channel layout of a pattern's pixels.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PatternColor {
+    /// One grey channel.
+    Greyscale,
+    /// A grey channel and an alpha channel.
+    GreyscaleAlpha,
+    /// Red, green and blue channels.
+    Rgb,
+    /// Red, green, blue and alpha channels.
+    Rgba,
+}
+
+impl PatternColor {
+    /// Number of channels per pixel.
+    pub fn channels(self) -> usize {
+        match self {
+            PatternColor::Greyscale => 1,
+            PatternColor::GreyscaleAlpha => 2,
+            PatternColor::Rgb => 3,
+            PatternColor::Rgba => 4,
+        }
+    }
+}
+
+// Image modes (same as in PSD files).
+const MODE_GREYSCALE: u32 = 1;
+const MODE_INDEXED: u32 = 2;
+const MODE_RGB: u32 = 3;
+
+/// Reads all the patterns in a `patt` section running from `start` to `end`.
+pub fn read_patterns<R: Read + Seek>(rdr: &mut R, start: u64, end: u64)
+                                     -> Vec<Result<Pattern, PatternError>> {
+    find_patterns(rdr, start, end)
+        .into_iter()
+        .map(|found| {
+            let (data_pos, pattern_end) = found?;
+            rdr.seek(SeekFrom::Start(data_pos))?;
+            read_pattern(rdr, pattern_end)
+        })
+        .collect()
+}
+
+/// Finds the patterns in a `patt` section running from `start` to `end`
+/// from their lengths alone, without reading them. Gives where each one's
+/// data starts and where it ends. An `Err` means the next pattern couldn't
+/// be found, and is the last item.
+pub fn find_patterns<R: Read + Seek>(rdr: &mut R, start: u64, end: u64)
+                                     -> Vec<Result<(u64, u64), io::Error>> {
+    let mut found = vec![];
+    let mut pos = start;
+    while pos + 4 <= end {
+        let pattern_end = match read_len(rdr, pos) {
+            Ok(len) => pos + 4 + len,
+            Err(e) => {
+                // Can't find the next pattern, so stop here.
+                found.push(Err(e));
+                break;
+            }
+        };
+        found.push(Ok((pos + 4, pattern_end)));
+        // Patterns are aligned to 4-byte boundaries.
+        pos = (pattern_end + 3) & !3;
+    }
+    found
+}
+
+fn read_len<R: Read + Seek>(rdr: &mut R, pos: u64) -> Result<u64, io::Error> {
+    rdr.seek(SeekFrom::Start(pos))?;
+    Ok(rdr.read_u32::<BigEndian>()? as u64)
+}
+
+/// Reads one pattern, which ends at `end`.
+fn read_pattern<R: Read + Seek>(rdr: &mut R, end: u64) -> Result<Pattern, PatternError> {
+    let version = rdr.read_u32::<BigEndian>()?;
+    if version != 1 {
+        return Err(PatternError::MalformedHeader("unknown pattern version"));
+    }
+
+    let mode = rdr.read_u32::<BigEndian>()?;
+    if mode != MODE_GREYSCALE && mode != MODE_INDEXED && mode != MODE_RGB {
+        return Err(PatternError::UnsupportedMode { mode });
+    }
+    let _height = rdr.read_u16::<BigEndian>()?;
+    let _width = rdr.read_u16::<BigEndian>()?;
+    let name = util::read_unicode_string(rdr)?;
+    let uuid = util::read_pascal_string(&mut *rdr)?;
+
+    let palette = if mode == MODE_INDEXED {
+        let mut palette = vec![0; 256 * 3];
+        rdr.read_exact(&mut palette)?;
+        // Unknown (number of colors and transparent index?).
+        rdr.seek(SeekFrom::Current(4))?;
+        Some(palette)
+    } else {
+        None
+    };
+
+    // The pixels are in a "virtual memory array list".
+    let vmal_version = rdr.read_u32::<BigEndian>()?;
+    if vmal_version != 3 {
+        return Err(PatternError::MalformedHeader("unknown VMA list version"));
+    }
+    let _vmal_len = rdr.read_u32::<BigEndian>()?;
+    let top = rdr.read_u32::<BigEndian>()?;
+    let left = rdr.read_u32::<BigEndian>()?;
+    let bottom = rdr.read_u32::<BigEndian>()?;
+    let right = rdr.read_u32::<BigEndian>()?;
+    if bottom < top || right < left {
+        return Err(PatternError::MalformedHeader("bad bounds"));
+    }
+    let width = right - left;
+    let height = bottom - top;
+    let num_channels = rdr.read_u32::<BigEndian>()?;
+
+    // There are two more arrays than channels (for masks). Only some of
+    // them are written: the color channels, then alpha if there is one.
+    let color_channels = if mode == MODE_RGB { 3 } else { 1 };
+    let mut channels = vec![];
+    let mut depth = None;
+    for _ in 0..num_channels as u64 + 2 {
+        if util::tell(rdr)? >= end || channels.len() == color_channels + 1 {
+            break;
+        }
+        if let Some(channel) = read_channel(rdr, width, height)? {
+            if *depth.get_or_insert(channel.depth) != channel.depth {
+                return Err(PatternError::MalformedHeader("mixed channel depths"));
+            }
+            channels.push(channel.data);
+        }
+    }
+    if channels.len() < color_channels {
+        return Err(PatternError::MalformedHeader("missing channels"));
+    }
+    let has_alpha = channels.len() > color_channels;
+    let depth = depth.unwrap_or(8);
+
+    let (color, data) = match palette {
+        Some(palette) => {
+            if depth != 8 {
+                return Err(PatternError::UnsupportedBitDepth { depth });
+            }
+            let color = if has_alpha { PatternColor::Rgba } else { PatternColor::Rgb };
+            (color, expand_indexed(&channels, &palette))
+        }
+        None => {
+            let color = match (color_channels, has_alpha) {
+                (1, false) => PatternColor::Greyscale,
+                (1, true) => PatternColor::GreyscaleAlpha,
+                (_, false) => PatternColor::Rgb,
+                (_, true) => PatternColor::Rgba,
+            };
+            (color, interleave(&channels, depth as usize >> 3))
+        }
+    };
+
+    Ok(Pattern { name, uuid, width, height, depth, color, data })
+}
+
+/// The samples of one channel.
+struct Channel {
+    depth: u16,
+    data: Vec<u8>,
+}
+
+/// Reads one "virtual memory array". Returns `None` if it is not written.
+fn read_channel<R: Read + Seek>(rdr: &mut R, width: u32, height: u32)
+                                -> Result<Option<Channel>, PatternError> {
+    let is_written = rdr.read_u32::<BigEndian>()?;
+    if is_written == 0 {
+        return Ok(None);
+    }
+    let len = rdr.read_u32::<BigEndian>()? as u64;
+    if len == 0 {
+        return Ok(None);
+    }
+    let channel_end = util::tell(rdr)? + len;
+
+    let _depth = rdr.read_u32::<BigEndian>()?;
+    let top = rdr.read_u32::<BigEndian>()?;
+    let left = rdr.read_u32::<BigEndian>()?;
+    let bottom = rdr.read_u32::<BigEndian>()?;
+    let right = rdr.read_u32::<BigEndian>()?;
+    if bottom.wrapping_sub(top) != height || right.wrapping_sub(left) != width {
+        return Err(PatternError::MalformedHeader("channel size doesn't match pattern"));
+    }
+
+    let depth = rdr.read_u16::<BigEndian>()?;
+    if depth != 8 && depth != 16 {
+        return Err(PatternError::UnsupportedBitDepth { depth });
+    }
+    let compressed = rdr.read_u8()? != 0;
+
+    let size = (width as usize) * (height as usize) * (depth as usize >> 3);
+    let data = if compressed {
+        // Be forgiving of RLE data that decodes to the wrong size.
+        util::read_rle_data_lenient(&mut *rdr, height, size)?
+    } else {
+        if util::tell(rdr)? + size as u64 > channel_end {
+            return Err(PatternError::MalformedHeader("channel data overruns array"));
+        }
+        let mut v = vec![0; size];
+        rdr.read_exact(&mut v)?;
+        v
+    };
+
+    rdr.seek(SeekFrom::Start(channel_end))?;
+    Ok(Some(Channel { depth, data }))
+}
+
+/// Interleaves planar channels with `bytes` bytes per sample.
+fn interleave(channels: &[Vec<u8>], bytes: usize) -> Vec<u8> {
+    let samples = channels[0].len() / bytes;
+    let mut data = Vec::with_capacity(channels[0].len() * channels.len());
+    for i in 0..samples {
+        for channel in channels {
+            data.extend_from_slice(&channel[i * bytes..(i + 1) * bytes]);
+        }
+    }
+    data
+}
+
+/// Looks up indexed pixels in `palette`, giving RGB (or RGBA, if there is
+/// an alpha channel after the index channel).
+fn expand_indexed(channels: &[Vec<u8>], palette: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(channels[0].len() * (2 + channels.len()));
+    for (i, &idx) in channels[0].iter().enumerate() {
+        let idx = idx as usize * 3;
+        data.extend_from_slice(&palette[idx..idx + 3]);
+        if let Some(alpha) = channels.get(1) {
+            data.push(alpha[i]);
+        }
+    }
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use super::*;
+    use super::super::byteorder::WriteBytesExt;
+
+    /// A greyscale pattern with one RLE-compressed channel whose rows are
+    /// `rows`, however long they are.
+    fn grey_pattern(width: u32, height: u32, rows: &[&[u8]]) -> Vec<u8> {
+        let mut channel = vec![];
+        channel.write_u32::<BigEndian>(8).unwrap();
+        for &n in &[0, 0, height, width] {
+            channel.write_u32::<BigEndian>(n).unwrap();
+        }
+        channel.write_u16::<BigEndian>(8).unwrap();
+        channel.write_u8(1).unwrap();
+        for row in rows {
+            channel.write_u16::<BigEndian>(row.len() as u16 + 1).unwrap();
+        }
+        for row in rows {
+            channel.push(row.len() as u8 - 1);
+            channel.extend_from_slice(row);
+        }
+
+        let mut vmal = vec![];
+        for &n in &[0, 0, height, width, 24, 1, channel.len() as u32] {
+            vmal.write_u32::<BigEndian>(n).unwrap();
+        }
+        vmal.extend(channel);
+        for _ in 0..25 {
+            vmal.write_u32::<BigEndian>(0).unwrap();
+        }
+
+        let mut pattern = vec![];
+        for &n in &[1, MODE_GREYSCALE] {
+            pattern.write_u32::<BigEndian>(n).unwrap();
+        }
+        pattern.write_u16::<BigEndian>(height as u16).unwrap();
+        pattern.write_u16::<BigEndian>(width as u16).unwrap();
+        util::write_unicode_string(&mut pattern, "Short").unwrap();
+        util::write_pascal_string(&mut pattern, "short-uuid").unwrap();
+        pattern.write_u32::<BigEndian>(3).unwrap();
+        pattern.write_u32::<BigEndian>(vmal.len() as u32).unwrap();
+        pattern.extend(vmal);
+
+        let mut section = vec![];
+        section.write_u32::<BigEndian>(pattern.len() as u32).unwrap();
+        section.extend(pattern);
+        section
+    }
+
+    #[test]
+    fn short_rle_channel_is_padded() {
+        let section = grey_pattern(4, 2, &[&[1, 2, 3], &[4, 5, 6]]);
+        let end = section.len() as u64;
+        let patterns = read_patterns(&mut Cursor::new(section), 0, end);
+        assert_eq!(patterns.len(), 1);
+        let pattern = patterns[0].as_ref().unwrap();
+        assert_eq!((pattern.width, pattern.height), (4, 2));
+        assert_eq!(pattern.color, PatternColor::Greyscale);
+        assert_eq!(pattern.data, [1, 2, 3, 4, 5, 6, 0, 0]);
+    }
+
+    #[test]
+    fn long_rle_channel_is_cut_short() {
+        let section = grey_pattern(2, 2, &[&[1, 2, 3], &[4, 5, 6]]);
+        let end = section.len() as u64;
+        let patterns = read_patterns(&mut Cursor::new(section), 0, end);
+        assert_eq!(patterns[0].as_ref().unwrap().data, [1, 2, 3, 4]);
+    }
+}
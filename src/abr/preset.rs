@@ -0,0 +1,87 @@
+use super::ImageBrush;
+use super::desc::{Descriptor, Value};
+
+/// A brush preset from an ABR6 file's `desc` section.
+///
+/// The numeric fields are taken from the preset's brush tip and are `None`
+/// when the tip doesn't record them.
+#[derive(Debug, Clone)]
+pub struct BrushPreset {
+    /// Preset name.
+    pub name: String,
+    /// Tip diameter, in pixels.
+    pub diameter: Option<f64>,
+    /// Spacing, as a percentage of the diameter. `None` if spacing is
+    /// turned off.
+    pub spacing: Option<f64>,
+    /// Tip angle, in degrees.
+    pub angle: Option<f64>,
+    /// Tip roundness, as a percentage.
+    pub roundness: Option<f64>,
+    /// Tip hardness, as a percentage (computed tips only).
+    pub hardness: Option<f64>,
+    /// Whether the tip is painted anti-aliased (sampled tips only).
+    pub antialias: Option<bool>,
+    /// UUID of the sampled tip in the `samp` section this preset uses, if
+    /// it uses one.
+    pub sampled_data: Option<String>,
+    /// The complete preset descriptor, for anything not pulled out above.
+    pub descriptor: Descriptor,
+}
+
+impl BrushPreset {
+    /// Whether this preset's tip is the sampled brush `brush`.
+    pub fn uses(&self, brush: &ImageBrush) -> bool {
+        match (&self.sampled_data, &brush.uuid) {
+            (Some(a), Some(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+/// Extracts the brush presets from the descriptor in a `desc` section.
+pub fn presets_from_descriptor(desc: &Descriptor) -> Vec<BrushPreset> {
+    let list = match desc.get("Brsh").and_then(Value::as_list) {
+        Some(list) => list,
+        None => return vec![],
+    };
+
+    list.iter()
+        .filter_map(Value::as_descriptor)
+        .map(preset_from_descriptor)
+        .collect()
+}
+
+fn preset_from_descriptor(desc: &Descriptor) -> BrushPreset {
+    let name = desc.get("Nm  ")
+        .and_then(Value::as_str)
+        .unwrap_or("")
+        .to_string();
+
+    // The tip parameters live in a nested brush descriptor.
+    let tip = desc.get("Brsh").and_then(Value::as_descriptor);
+    let get_f64 = |key: &str| {
+        tip.and_then(|tip| tip.get(key)).and_then(Value::as_f64)
+    };
+
+    let spacing_on = tip.and_then(|tip| tip.get("Intr"))
+        .and_then(Value::as_bool)
+        .unwrap_or(true);
+    let spacing = if spacing_on { get_f64("Spcn") } else { None };
+
+    let sampled_data = tip.and_then(|tip| tip.get("sampledData"))
+        .and_then(Value::as_str)
+        .map(|s| s.to_string());
+
+    BrushPreset {
+        name,
+        diameter: get_f64("Dmtr"),
+        spacing,
+        angle: get_f64("Angl"),
+        roundness: get_f64("Rndn"),
+        hardness: get_f64("Hrdn"),
+        antialias: tip.and_then(|tip| tip.get("AntA")).and_then(Value::as_bool),
+        sampled_data,
+        descriptor: desc.clone(),
+    }
+}
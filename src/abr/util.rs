@@ -1,18 +1,87 @@
 use std;
-use std::io::{self, Read, Seek, SeekFrom};
-use super::byteorder::{BigEndian, ReadBytesExt};
+use std::convert::TryInto;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use super::byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use super::{BrushHeader, ImageBrush};
 
 /// Get the current location in a seekable stream.
 pub fn tell<R: Seek>(rdr: &mut R) -> std::io::Result<u64> {
-    rdr.seek(SeekFrom::Current(0))
+    rdr.stream_position()
 }
 
-/// Read `height` rows of run-length compressed data into a vector.
-/// `size_hint` is a guess at the size of the uncompressed data.
-pub fn read_rle_data<R: Read>(mut rdr: R,
-                              height: u32,
-                              size_hint: usize)
-                              -> Result<Vec<u8>, io::Error> {
+/// Read a Pascal string (a u8 length followed by that many bytes).
+pub fn read_pascal_string<R: Read>(mut rdr: R) -> Result<String, io::Error> {
+    let len = rdr.read_u8()? as usize;
+    let mut buf = vec![0; len];
+    rdr.read_exact(&mut buf)?;
+    Ok(String::from_utf8_lossy(&buf).into_owned())
+}
+
+/// Write a Pascal string. Strings longer than 255 bytes are truncated.
+pub fn write_pascal_string<W: Write>(mut w: W, s: &str) -> Result<(), io::Error> {
+    let bytes = &s.as_bytes()[..s.len().min(255)];
+    w.write_u8(bytes.len() as u8)?;
+    w.write_all(bytes)
+}
+
+/// Read a length-prefixed UTF-16 string, dropping any trailing NULs.
+pub fn read_unicode_string<R: Read>(rdr: &mut R) -> Result<String, io::Error> {
+    let len = rdr.read_u32::<BigEndian>()?;
+    let mut units = vec![];
+    for _ in 0..len {
+        units.push(rdr.read_u16::<BigEndian>()?);
+    }
+    while units.last() == Some(&0) {
+        units.pop();
+    }
+    Ok(String::from_utf16_lossy(&units))
+}
+
+/// Write a length-prefixed UTF-16 string. Like Photoshop, this includes a
+/// trailing NUL.
+pub fn write_unicode_string<W: Write>(w: &mut W, s: &str) -> Result<(), io::Error> {
+    let units = s.encode_utf16().chain(Some(0)).collect::<Vec<u16>>();
+    w.write_u32::<BigEndian>(units.len() as u32)?;
+    for unit in units {
+        w.write_u16::<BigEndian>(unit)?;
+    }
+    Ok(())
+}
+
+/// Reads the samples of the image brush `header` describes, and makes the
+/// brush.
+pub fn read_image<R: Read + Seek>(rdr: &mut R, header: &BrushHeader) -> io::Result<ImageBrush> {
+    let offset = header.samples_offset.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "brush has no samples")
+    })?;
+    let depth = header.depth.unwrap_or(8);
+    let size = (header.width as usize) * (header.height as usize) * (depth as usize >> 3);
+
+    rdr.seek(SeekFrom::Start(offset))?;
+    let data = if header.compressed {
+        read_rle_data(&mut *rdr, header.height, size)?
+    } else {
+        let mut v = vec![0; size];
+        rdr.read_exact(&mut v)?;
+        v
+    };
+
+    Ok(ImageBrush {
+        width: header.width,
+        height: header.height,
+        depth,
+        data,
+        uuid: header.uuid.clone(),
+        name: header.name.clone(),
+        spacing: header.spacing,
+        antialias: header.antialias,
+        compressed: header.compressed,
+    })
+}
+
+/// Read the table of row lengths at the start of `height` rows of
+/// run-length compressed data, and return the total length of the rows.
+pub fn read_rle_len<R: Read>(mut rdr: R, height: u32) -> Result<u64, io::Error> {
     // There are `height` u16s containing the RLE'd length of each of
     // the `height` scanlines.
     // We just need the total length.
@@ -20,29 +89,373 @@ pub fn read_rle_data<R: Read>(mut rdr: R,
     for _ in 0..height {
         len += rdr.read_u16::<BigEndian>()? as u64;
     }
+    Ok(len)
+}
+
+/// Read `height` rows of run-length compressed data into a vector. `size` is
+/// the size of the uncompressed data; it's decoded straight into a buffer of
+/// that size. Fails if the data doesn't decode to exactly that much.
+pub fn read_rle_data<R: Read>(rdr: R, height: u32, size: usize) -> Result<Vec<u8>, io::Error> {
+    let packed = read_packed(rdr, height)?;
+
+    // Each 2 bytes of compressed data make at most 128 bytes, so don't trust
+    // a size much bigger than that.
+    let mut data = vec![0; size.min(packed.len().saturating_mul(64))];
+    let written = unpack_bits(&packed, &mut data)?;
+    if written != size {
+        return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                  "RLE data doesn't match image size"));
+    }
+    Ok(data)
+}
 
-    // Decode RLE'd data.
-    let mut data = Vec::with_capacity(size_hint);
-    let mut bytes_read = 0;
-    while bytes_read < len {
-        let n = rdr.read_i8()?;
-        bytes_read += 1;
+/// Like `read_rle_data`, but data that decodes to the wrong size is
+/// zero-padded or cut short to `size` rather than refused.
+pub fn read_rle_data_lenient<R: Read>(rdr: R,
+                                      height: u32,
+                                      size: usize)
+                                      -> Result<Vec<u8>, io::Error> {
+    let packed = read_packed(rdr, height)?;
+
+    let mut data = vec![0; size.max(unpacked_len(&packed))];
+    let written = unpack_bits(&packed, &mut data)?;
+    // Whole-run copies may have scribbled past the end of the data.
+    data[written..].fill(0);
+    data.truncate(size);
+    Ok(data)
+}
+
+/// Read the row table and then the compressed data of `height` rows.
+fn read_packed<R: Read>(mut rdr: R, height: u32) -> Result<Vec<u8>, io::Error> {
+    let len = read_rle_len(&mut rdr, height)?;
+
+    // Read all the compressed data at once, rather than a byte at a time.
+    // Room is made for it up front, unless the table says it's implausibly
+    // long, in which case it's left to grow as it's read.
+    let mut packed = Vec::with_capacity(len.min(1 << 24) as usize);
+    rdr.take(len).read_to_end(&mut packed)?;
+    if (packed.len() as u64) < len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(packed)
+}
+
+/// How many bytes PackBits-compressed `src` decodes to, ignoring whether its
+/// last run is complete.
+fn unpacked_len(src: &[u8]) -> usize {
+    let mut i = 0;
+    let mut out = 0;
+    while i < src.len() {
+        let n = src[i] as i8;
+        i += 1;
         if n == -128 {
-            // NOP
+            continue;
         } else if n < 0 {
+            out += -n as usize + 1;
+            i += 1;
+        } else {
+            out += n as usize + 1;
+            i += n as usize + 1;
+        }
+    }
+    out
+}
+
+/// Decode PackBits-compressed `src` into `dst`. Returns how many bytes were
+/// written. Fails if `src` ends partway through a run, or decodes to more
+/// than fits in `dst`.
+pub fn unpack_bits(src: &[u8], dst: &mut [u8]) -> Result<usize, io::Error> {
+    // Runs are at most 128 bytes. Away from the ends of the buffers, copying
+    // a whole 128 bytes and then moving on by however many were meant is
+    // quicker than copying exactly that many, since most runs are short.
+    const MAX_RUN: usize = 128;
+
+    let mut i = 0;
+    let mut out = 0;
+    while i < src.len() {
+        let n = src[i] as i8;
+        i += 1;
+        if n == -128 {
+            // NOP
+            continue;
+        }
+
+        let fast = i + MAX_RUN <= src.len() && out + MAX_RUN <= dst.len();
+        if n < 0 {
             // RLE encoded. Repeat the next byte -n+1 times.
             let count = -n as usize + 1;
-            let b = rdr.read_u8()?;
-            bytes_read += 1;
-            data.extend(std::iter::repeat(b).take(count));
+            let b = *src.get(i).ok_or(io::ErrorKind::UnexpectedEof)?;
+            i += 1;
+            if fast {
+                fill_run(&mut dst[out..out + MAX_RUN], b);
+            } else {
+                dst.get_mut(out..out + count).ok_or_else(overrun)?.fill(b);
+            }
+            out += count;
         } else {
-            // Uncoded. Read the next n+1 bytes, raw, from the input.
+            // Uncoded. Copy the next n+1 bytes, raw, from the input.
             let count = n as usize + 1;
-            let off = data.len();
-            data.extend(std::iter::repeat(0).take(count));
-            rdr.read_exact(&mut data[off..])?;
-            bytes_read += count as u64;
+            if fast {
+                copy_run(&mut dst[out..out + MAX_RUN], &src[i..i + MAX_RUN]);
+            } else {
+                let run = src.get(i..i + count).ok_or(io::ErrorKind::UnexpectedEof)?;
+                dst.get_mut(out..out + count).ok_or_else(overrun)?.copy_from_slice(run);
+            }
+            i += count;
+            out += count;
         }
     }
-    Ok(data)
+    Ok(out)
+}
+
+fn overrun() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "RLE data overruns image")
+}
+
+/// Fills a whole run's worth of `dst` with `b`.
+fn fill_run(dst: &mut [u8], b: u8) {
+    let dst: &mut [u8; 128] = dst.try_into().unwrap();
+    *dst = [b; 128];
+}
+
+/// Copies a whole run's worth of `src` to `dst`.
+fn copy_run(dst: &mut [u8], src: &[u8]) {
+    let dst: &mut [u8; 128] = dst.try_into().unwrap();
+    let src: &[u8; 128] = src.try_into().unwrap();
+    *dst = *src;
+}
+
+/// Write `data` as `height` rows of run-length compressed data, in the form
+/// `read_rle_data` reads. Fails if a compressed row is too long for its
+/// length to fit in the row table.
+pub fn write_rle_data<W: Write>(mut w: W, data: &[u8], height: u32) -> Result<(), io::Error> {
+    let row_len = if height == 0 { 0 } else { data.len() / height as usize };
+
+    let mut lengths = vec![];
+    let mut packed = vec![];
+    for row in data.chunks(row_len.max(1)).take(height as usize) {
+        let start = packed.len();
+        pack_bits(row, &mut packed);
+        let len = packed.len() - start;
+        if len > u16::MAX as usize {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "RLE row too long"));
+        }
+        lengths.push(len as u16);
+    }
+    // Rows the data ran out before are empty.
+    lengths.resize(height as usize, 0);
+
+    for len in lengths {
+        w.write_u16::<BigEndian>(len)?;
+    }
+    w.write_all(&packed)
+}
+
+/// PackBits-compress one row onto the end of `out`.
+pub fn pack_bits(row: &[u8], out: &mut Vec<u8>) {
+    let mut i = 0;
+    while i < row.len() {
+        // Length of the run starting here.
+        let mut run = 1;
+        while i + run < row.len() && run < 128 && row[i + run] == row[i] {
+            run += 1;
+        }
+
+        if run >= 2 {
+            // Repeat the next byte -n+1 times.
+            out.push((1 - run as i32) as i8 as u8);
+            out.push(row[i]);
+            i += run;
+        } else {
+            // Copy bytes literally until the next run of 3 or more (a run of
+            // two isn't worth breaking a literal for).
+            let start = i;
+            while i < row.len() && i - start < 128 {
+                if i + 2 < row.len() && row[i] == row[i + 1] && row[i] == row[i + 2] {
+                    break;
+                }
+                i += 1;
+            }
+            out.push((i - start - 1) as u8);
+            out.extend_from_slice(&row[start..i]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A literal run of `bytes`.
+    fn literal(bytes: &[u8]) -> Vec<u8> {
+        let mut v = vec![(bytes.len() - 1) as u8];
+        v.extend_from_slice(bytes);
+        v
+    }
+
+    /// A run repeating `b` `count` times.
+    fn repeat(b: u8, count: usize) -> Vec<u8> {
+        vec![(1 - count as i32) as i8 as u8, b]
+    }
+
+    fn unpack(src: &[u8], size: usize) -> io::Result<Vec<u8>> {
+        let mut dst = vec![0xaa; size];
+        let written = unpack_bits(src, &mut dst)?;
+        dst.truncate(written);
+        Ok(dst)
+    }
+
+    #[test]
+    fn unpack_fast_path() {
+        // Plenty of room after each run, so they're copied 128 bytes at a time.
+        let mut src = vec![];
+        src.extend(repeat(3, 128));
+        src.extend(literal(&(0..128).collect::<Vec<u8>>()));
+        src.extend(repeat(4, 2));
+        src.extend(literal(&[5]));
+        // No-ops, so every run is well away from the end of src.
+        src.extend(vec![0x80; 300]);
+        let mut expected = vec![3; 128];
+        expected.extend(0..128);
+        expected.extend(&[4, 4, 5]);
+
+        let mut dst = vec![0xaa; 1000];
+        assert_eq!(unpack_bits(&src, &mut dst).unwrap(), expected.len());
+        assert_eq!(&dst[..expected.len()], &expected[..]);
+    }
+
+    #[test]
+    fn unpack_exact_copy() {
+        // Too little room for the fast path anywhere.
+        let mut src = repeat(7, 3);
+        src.extend(literal(&[1, 2]));
+        assert_eq!(unpack(&src, 5).unwrap(), vec![7, 7, 7, 1, 2]);
+    }
+
+    #[test]
+    fn unpack_fast_path_near_the_end() {
+        // The first runs are taken fast, the last ones exactly, as they come
+        // within 128 bytes of the end of src and then dst.
+        let bytes = (0..=255).collect::<Vec<u8>>();
+        let mut src = vec![];
+        let mut expected = vec![];
+        for chunk in bytes.chunks(100) {
+            src.extend(literal(chunk));
+            expected.extend(chunk);
+        }
+        for &(b, count) in &[(1, 128), (2, 2), (3, 100)] {
+            src.extend(repeat(b, count));
+            expected.extend(vec![b; count]);
+        }
+        assert_eq!(unpack(&src, expected.len()).unwrap(), expected);
+        assert!(unpack(&src, expected.len() - 1).is_err());
+    }
+
+    #[test]
+    fn unpack_nop() {
+        let src = [0x80, 0, 9, 0x80, 0xff, 8, 0x80];
+        assert_eq!(unpack(&src, 3).unwrap(), vec![9, 8, 8]);
+        assert_eq!(unpack(&[0x80], 0).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn unpack_overrun() {
+        let err = unpack(&repeat(1, 10), 9).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let err = unpack(&literal(&[1, 2, 3]), 2).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn unpack_truncated() {
+        let err = unpack(&[0xfe], 10).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        let err = unpack(&[4, 1, 2], 10).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    /// The row table for `rows`, followed by the rows.
+    fn rle_rows(rows: &[Vec<u8>]) -> Vec<u8> {
+        let mut v = vec![];
+        for row in rows {
+            v.write_u16::<BigEndian>(row.len() as u16).unwrap();
+        }
+        for row in rows {
+            v.extend_from_slice(row);
+        }
+        v
+    }
+
+    #[test]
+    fn read_rle_data_checks_size() {
+        let rows = vec![repeat(1, 4), literal(&[2, 3, 4, 5])];
+        let file = rle_rows(&rows);
+        assert_eq!(read_rle_data(&file[..], 2, 8).unwrap(), vec![1, 1, 1, 1, 2, 3, 4, 5]);
+
+        let err = read_rle_data(&file[..], 2, 9).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let err = read_rle_data(&file[..], 2, 7).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let err = read_rle_data(&file[..file.len() - 1], 2, 8).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    fn pack(row: &[u8]) -> Vec<u8> {
+        let mut out = vec![];
+        pack_bits(row, &mut out);
+        let mut unpacked = vec![0; row.len()];
+        assert_eq!(unpack_bits(&out, &mut unpacked).unwrap(), row.len());
+        assert_eq!(unpacked, row);
+        out
+    }
+
+    #[test]
+    fn pack_bits_empty_row() {
+        assert_eq!(pack(&[]), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn pack_bits_run_of_two() {
+        assert_eq!(pack(&[7, 7]), vec![0xff, 7]);
+        assert_eq!(pack(&[7, 7, 1]), vec![0xff, 7, 0, 1]);
+        // A run of two isn't worth ending a literal for.
+        assert_eq!(pack(&[1, 7, 7]), vec![2, 1, 7, 7]);
+    }
+
+    #[test]
+    fn pack_bits_run_of_128() {
+        assert_eq!(pack(&[9; 128]), vec![0x81, 9]);
+    }
+
+    #[test]
+    fn pack_bits_run_of_129() {
+        // The longest run is 128, so the last byte is a literal.
+        assert_eq!(pack(&[9; 129]), vec![0x81, 9, 0, 9]);
+        assert_eq!(pack(&[9; 130]), vec![0x81, 9, 0xff, 9]);
+    }
+
+    #[test]
+    fn pack_bits_alternating() {
+        let row = (0..300).map(|i| (i % 2) as u8).collect::<Vec<_>>();
+        let out = pack(&row);
+        // Literals of at most 128 bytes, each with a header.
+        assert_eq!(out[0], 127);
+        assert_eq!(out[129], 127);
+        assert_eq!(out[258], 43);
+        assert_eq!(out.len(), 300 + 3);
+    }
+
+    #[test]
+    fn rle_round_trip_16_bit() {
+        // 16-bit rows are width * 2 bytes long.
+        let (width, height) = (70, 3);
+        let data = (0..width * height * 2)
+            .map(|i| if i < width * 2 { 5 } else { (i * 7 % 251) as u8 })
+            .collect::<Vec<u8>>();
+        let mut file = vec![];
+        write_rle_data(&mut file, &data, height as u32).unwrap();
+        assert_eq!(read_rle_len(&file[..], height as u32).unwrap() as usize,
+                   file.len() - height * 2);
+        assert_eq!(read_rle_data(&file[..], height as u32, data.len()).unwrap(), data);
+    }
 }
@@ -0,0 +1,283 @@
+//! Writer for ABR6 files.
+
+use md5;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use super::byteorder::{BigEndian, WriteBytesExt};
+use super::desc::{self, Descriptor, Value};
+use super::util;
+use super::ImageBrush;
+
+/// Options for writing an ABR file.
+#[derive(Debug, Clone, Default)]
+pub struct WriteOptions {
+    /// Leave out the `desc` section. It holds the brush presets, which is
+    /// where ABR6 keeps brush names and spacing, so those are lost.
+    pub omit_presets: bool,
+}
+
+/// Spacing to give brushes that don't have any.
+const DEFAULT_SPACING: u16 = 25;
+
+/// Version of the action descriptor format.
+const DESCRIPTOR_VERSION: u32 = 16;
+
+/// Saves `brushes` as an ABR file at `path`. See `write`.
+pub fn save(path: &Path, brushes: &[ImageBrush], options: &WriteOptions) -> io::Result<()> {
+    let mut w = BufWriter::new(File::create(path)?);
+    write(&mut w, brushes, options)?;
+    w.flush()
+}
+
+/// Writes `brushes` to `w` as a version 6.2 ABR file.
+///
+/// The tip images go in a `samp` block, run-length compressed where that
+/// makes them smaller. Unless `options` says otherwise, a `desc` block
+/// follows with a preset for each brush giving its name and spacing. Brushes
+/// without a UUID are given one made from their contents.
+pub fn write<W: Write>(mut w: W, brushes: &[ImageBrush], options: &WriteOptions)
+                       -> io::Result<()> {
+    let uuids = brushes.iter()
+        .enumerate()
+        .map(|(idx, brush)| brush.uuid.clone().unwrap_or_else(|| make_uuid(idx, brush)))
+        .collect::<Vec<_>>();
+
+    let mut samp = vec![];
+    let mut entry = vec![];
+    for (brush, uuid) in brushes.iter().zip(&uuids) {
+        entry.clear();
+        write_sampled_brush(&mut entry, brush, uuid)?;
+        samp.write_u32::<BigEndian>(to_u32(entry.len())?)?;
+        samp.extend_from_slice(&entry);
+        // Brushes are aligned to 4-byte boundaries.
+        while samp.len() % 4 != 0 {
+            samp.push(0);
+        }
+    }
+
+    w.write_u16::<BigEndian>(6)?; // version
+    w.write_u16::<BigEndian>(2)?; // subversion
+    write_block(&mut w, b"samp", &samp)?;
+
+    if !options.omit_presets {
+        let mut section = vec![];
+        section.write_u32::<BigEndian>(DESCRIPTOR_VERSION)?;
+        desc::write_descriptor(&mut section, &presets_descriptor(brushes, &uuids))?;
+        write_block(&mut w, b"desc", &section)?;
+    }
+
+    Ok(())
+}
+
+/// Writes an 8BIM block, or an 8B64 block if it's too big for that.
+fn write_block<W: Write>(w: &mut W, key: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    match to_u32(data.len()) {
+        Ok(len) => {
+            w.write_all(b"8BIM")?;
+            w.write_all(key)?;
+            w.write_u32::<BigEndian>(len)?;
+        }
+        Err(_) => {
+            w.write_all(b"8B64")?;
+            w.write_all(key)?;
+            w.write_u64::<BigEndian>(data.len() as u64)?;
+        }
+    }
+    w.write_all(data)
+}
+
+/// Writes one brush of the `samp` block, without its length.
+fn write_sampled_brush(w: &mut Vec<u8>, brush: &ImageBrush, uuid: &str) -> io::Result<()> {
+    if brush.depth != 8 && brush.depth != 16 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "brush isn't 8- or 16-bit"));
+    }
+    let size = (brush.width as usize) * (brush.height as usize) * (brush.depth as usize >> 3);
+    if brush.data.len() != size {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                  "data doesn't match brush size"));
+    }
+
+    util::write_pascal_string(&mut *w, uuid)?;
+    // We don't know what goes in here; readers skip it.
+    w.extend_from_slice(&[0; 264]);
+
+    w.write_u32::<BigEndian>(0)?; // top
+    w.write_u32::<BigEndian>(0)?; // left
+    w.write_u32::<BigEndian>(brush.height)?; // bottom
+    w.write_u32::<BigEndian>(brush.width)?; // right
+    w.write_u16::<BigEndian>(brush.depth)?;
+
+    // Compress unless that doesn't help (or the rows are too long to).
+    let mut rle = vec![];
+    let compressed = util::write_rle_data(&mut rle, &brush.data, brush.height).is_ok() &&
+                     rle.len() < brush.data.len();
+    w.write_u8(compressed as u8)?;
+    w.extend_from_slice(if compressed { &rle } else { &brush.data });
+    Ok(())
+}
+
+/// Builds a `desc` descriptor with a preset for each brush.
+fn presets_descriptor(brushes: &[ImageBrush], uuids: &[String]) -> Descriptor {
+    let presets = brushes.iter()
+        .zip(uuids)
+        .enumerate()
+        .map(|(idx, (brush, uuid))| {
+            let name = brush.name.clone().unwrap_or_else(|| format!("Brush {}", idx + 1));
+            let spacing = brush.spacing.unwrap_or(DEFAULT_SPACING);
+
+            let mut tip = vec![
+                ("Dmtr".to_string(), unit_float("#Pxl", brush.width.max(brush.height) as f64)),
+                ("Angl".to_string(), unit_float("#Ang", 0.0)),
+                ("Rndn".to_string(), unit_float("#Prc", 100.0)),
+                ("Spcn".to_string(), unit_float("#Prc", spacing as f64)),
+                ("Intr".to_string(), Value::Bool(true)),
+                ("flipX".to_string(), Value::Bool(false)),
+                ("flipY".to_string(), Value::Bool(false)),
+            ];
+            if let Some(antialias) = brush.antialias {
+                tip.push(("AntA".to_string(), Value::Bool(antialias)));
+            }
+            tip.push(("sampledData".to_string(), Value::Text(uuid.clone())));
+
+            Value::Descriptor(Descriptor {
+                name: String::new(),
+                class_id: "brushPreset".to_string(),
+                items: vec![
+                    ("Nm  ".to_string(), Value::Text(name)),
+                    ("Brsh".to_string(), Value::Descriptor(Descriptor {
+                        name: String::new(),
+                        class_id: "sampledBrush".to_string(),
+                        items: tip,
+                    })),
+                ],
+            })
+        })
+        .collect();
+
+    Descriptor {
+        name: String::new(),
+        class_id: "null".to_string(),
+        items: vec![("Brsh".to_string(), Value::List(presets))],
+    }
+}
+
+fn unit_float(unit: &str, value: f64) -> Value {
+    Value::UnitFloat { unit: unit.to_string(), value }
+}
+
+/// Makes a UUID for a brush from its position and contents, so writing the
+/// same brushes twice gives the same file.
+fn make_uuid(idx: usize, brush: &ImageBrush) -> String {
+    let mut ctx = md5::Context::new();
+    ctx.consume((idx as u64).to_be_bytes());
+    ctx.consume(brush.width.to_be_bytes());
+    ctx.consume(brush.height.to_be_bytes());
+    ctx.consume(&brush.data);
+    let hex = format!("{:x}", ctx.compute());
+    format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+}
+
+fn to_u32(x: usize) -> io::Result<u32> {
+    if x as u64 > u32::MAX as u64 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "too large for ABR"));
+    }
+    Ok(x as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use super::super::{open, Brush, ImageBrush};
+    use super::*;
+
+    /// A brush of `depth` bits. Noisy data won't compress, flat data will.
+    fn brush(name: &str, width: u32, height: u32, depth: u16, noisy: bool) -> ImageBrush {
+        let size = (width * height) as usize * (depth as usize >> 3);
+        let mut seed = 0x1234_5678u32;
+        let data = (0..size)
+            .map(|i| {
+                if noisy {
+                    seed ^= seed << 13;
+                    seed ^= seed >> 17;
+                    seed ^= seed << 5;
+                    seed as u8
+                } else {
+                    (i / 64) as u8
+                }
+            })
+            .collect();
+        ImageBrush {
+            width,
+            height,
+            depth,
+            data,
+            uuid: None,
+            name: Some(name.to_string()),
+            spacing: Some(40),
+            antialias: Some(true),
+            compressed: false,
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut brushes = vec![
+            brush("raw 8", 13, 7, 8, true),
+            brush("rle 8", 100, 20, 8, false),
+            brush("raw 16", 9, 11, 16, true),
+            brush("rle 16", 64, 30, 16, false),
+        ];
+        brushes[0].uuid = Some("given-uuid".to_string());
+
+        let mut file = vec![];
+        write(&mut file, &brushes, &WriteOptions::default()).unwrap();
+
+        let mut read = open(Cursor::new(&file[..])).unwrap();
+        assert_eq!((read.version(), read.subversion()), (6, Some(2)));
+        let presets = read.presets().unwrap();
+        let read_back = read.map(|b| match b.unwrap() {
+                Brush::Image(b) => b,
+                Brush::Computed(_) => panic!("computed brush"),
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(read_back.len(), brushes.len());
+        assert_eq!(presets.len(), brushes.len());
+        for ((old, new), preset) in brushes.iter().zip(&read_back).zip(&presets) {
+            assert_eq!((new.width, new.height, new.depth), (old.width, old.height, old.depth));
+            assert_eq!(new.data, old.data);
+            assert_eq!(new.compressed, old.name.as_ref().unwrap().starts_with("rle"));
+            assert!(preset.uses(new));
+            assert_eq!(Some(&preset.name), old.name.as_ref());
+            assert_eq!(preset.spacing, Some(40.0));
+            assert_eq!(preset.antialias, Some(true));
+        }
+        assert_eq!(read_back[0].uuid.as_ref().map(|s| &s[..]), Some("given-uuid"));
+    }
+
+    #[test]
+    fn round_trip_without_presets() {
+        let brushes = vec![brush("a", 5, 5, 8, false)];
+        let mut file = vec![];
+        write(&mut file, &brushes, &WriteOptions { omit_presets: true }).unwrap();
+
+        let mut read = open(Cursor::new(&file[..])).unwrap();
+        assert!(read.presets().unwrap().is_empty());
+        let brush = read.next().unwrap().unwrap().into_image();
+        assert_eq!(brush.data, brushes[0].data);
+        assert!(read.next().is_none());
+    }
+
+    #[test]
+    fn bad_brushes_are_refused() {
+        let mut bad_depth = brush("a", 2, 2, 8, false);
+        bad_depth.depth = 32;
+        let mut bad_size = brush("b", 2, 2, 8, false);
+        bad_size.data.pop();
+        for bad in [bad_depth, bad_size] {
+            let err = write(&mut vec![], &[bad], &WriteOptions::default()).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+}
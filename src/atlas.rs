@@ -0,0 +1,385 @@
+//! Packing brush tips into texture atlases.
+//!
+//! Tips are packed with a skyline bottom-left packer into as many
+//! power-of-two pages as they need. Each tip can have its edge pixels
+//! extruded outwards, and padding left around it, so texture filtering
+//! doesn't bleed neighbouring tips into each other. `write_json` describes
+//! where everything went, in a form close to TexturePacker's JSON array
+//! format.
+
+use json;
+use std::cmp::Reverse;
+use std::io::{self, Write};
+
+/// Settings for packing an atlas.
+#[derive(Debug, Clone)]
+pub struct AtlasParams {
+    /// Maximum width and height of a page. Should be a power of two.
+    pub max_size: u32,
+    /// Empty pixels between tips (outside any extrusion).
+    pub padding: u32,
+    /// How many pixels to repeat each tip's edge outwards by.
+    pub extrude: u32,
+}
+
+impl Default for AtlasParams {
+    fn default() -> AtlasParams {
+        AtlasParams { max_size: 2048, padding: 2, extrude: 1 }
+    }
+}
+
+/// A tip to pack: 8-bit samples, `width`×`height`, 0 is transparent.
+pub struct Sprite<'a> {
+    pub data: &'a [u8],
+    pub width: u32,
+    pub height: u32,
+    /// Name written in the JSON.
+    pub name: &'a str,
+    /// Index written in the JSON, eg. of the brush in its file.
+    pub index: usize,
+}
+
+/// Where a sprite went.
+#[derive(Debug, Clone, Copy)]
+pub struct Placement {
+    /// Index of the page it's on.
+    pub page: usize,
+    /// Left of the tip on the page, not counting extrusion.
+    pub x: u32,
+    /// Top of the tip on the page, not counting extrusion.
+    pub y: u32,
+}
+
+/// One image of an atlas.
+pub struct Page {
+    pub width: u32,
+    pub height: u32,
+    /// Row-major 8-bit samples, 0 is transparent.
+    pub data: Vec<u8>,
+}
+
+/// A packed atlas.
+pub struct Atlas {
+    pub pages: Vec<Page>,
+    /// Where each sprite went, in the order they were given. `None` if it
+    /// was too big to fit on a page.
+    pub placements: Vec<Option<Placement>>,
+}
+
+/// A skyline: the height of the packed area along the page, as segments of
+/// (x, y, width).
+struct Skyline {
+    segments: Vec<(u32, u32, u32)>,
+    width: u32,
+    height: u32,
+}
+
+impl Skyline {
+    fn new(width: u32, height: u32) -> Skyline {
+        Skyline { segments: vec![(0, 0, width)], width, height }
+    }
+
+    /// Finds the lowest (then leftmost) place a `w`×`h` rectangle fits, and
+    /// claims it.
+    fn insert(&mut self, w: u32, h: u32) -> Option<(u32, u32)> {
+        let mut best: Option<(usize, u32, u32)> = None;
+        for i in 0..self.segments.len() {
+            let x = self.segments[i].0;
+            if x + w > self.width {
+                break;
+            }
+            // The rectangle rests on the highest segment under it.
+            let mut y = 0;
+            let mut covered = 0;
+            for &(_, seg_y, seg_w) in &self.segments[i..] {
+                y = y.max(seg_y);
+                covered += seg_w;
+                if covered >= w {
+                    break;
+                }
+            }
+            if y + h > self.height {
+                continue;
+            }
+            let better = match best {
+                Some((_, best_x, best_y)) => (y + h, x) < (best_y + h, best_x),
+                None => true,
+            };
+            if better {
+                best = Some((i, x, y));
+            }
+        }
+
+        let (i, x, y) = best?;
+        // Replace the segments under the rectangle with one on top of it.
+        let end = x + w;
+        let mut j = i;
+        while j < self.segments.len() && self.segments[j].0 < end {
+            j += 1;
+        }
+        let (last_x, last_y, last_w) = self.segments[j - 1];
+        let mut replacement = vec![(x, y + h, w)];
+        if last_x + last_w > end {
+            replacement.push((end, last_y, last_x + last_w - end));
+        }
+        self.segments.splice(i..j, replacement);
+        Some((x, y))
+    }
+}
+
+/// Packs `sprites` into pages no bigger than `params.max_size`.
+pub fn pack(sprites: &[Sprite], params: &AtlasParams) -> Atlas {
+    let border = params.extrude * 2 + params.padding;
+    // Padding only matters between tips, so let it hang off the page's
+    // right and bottom edges.
+    let space = params.max_size + params.padding;
+
+    // Packing tall sprites first packs tighter.
+    let mut order = (0..sprites.len()).collect::<Vec<_>>();
+    order.sort_by_key(|&i| Reverse((sprites[i].height, sprites[i].width)));
+
+    let mut skylines: Vec<Skyline> = vec![];
+    let mut placements = vec![None; sprites.len()];
+    for i in order {
+        let (w, h) = (sprites[i].width + border, sprites[i].height + border);
+        if w > space || h > space {
+            continue;
+        }
+        let mut placed = skylines.iter_mut()
+            .enumerate()
+            .filter_map(|(page, skyline)| skyline.insert(w, h).map(|pos| (page, pos)))
+            .next();
+        if placed.is_none() {
+            let mut skyline = Skyline::new(space, space);
+            placed = skyline.insert(w, h).map(|pos| (skylines.len(), pos));
+            skylines.push(skyline);
+        }
+        if let Some((page, (x, y))) = placed {
+            placements[i] = Some(Placement {
+                page,
+                x: x + params.extrude,
+                y: y + params.extrude,
+            });
+        }
+    }
+
+    // Shrink each page to the smallest power of two that holds its tips.
+    let mut sizes = vec![(1, 1); skylines.len()];
+    for (sprite, placement) in sprites.iter().zip(&placements) {
+        if let Some(p) = *placement {
+            let size = &mut sizes[p.page];
+            size.0 = size.0.max(p.x + sprite.width + params.extrude);
+            size.1 = size.1.max(p.y + sprite.height + params.extrude);
+        }
+    }
+    let mut pages = sizes.into_iter()
+        .map(|(w, h)| {
+            let (width, height) = (w.next_power_of_two(), h.next_power_of_two());
+            Page { width, height, data: vec![0; width as usize * height as usize] }
+        })
+        .collect::<Vec<_>>();
+
+    for (sprite, placement) in sprites.iter().zip(&placements) {
+        if let Some(p) = *placement {
+            blit(&mut pages[p.page], sprite, p.x, p.y, params.extrude);
+        }
+    }
+
+    Atlas { pages, placements }
+}
+
+/// Copies `sprite` onto `page` at (`x`, `y`), repeating its edge pixels
+/// `extrude` pixels outwards.
+fn blit(page: &mut Page, sprite: &Sprite, x: u32, y: u32, extrude: u32) {
+    if sprite.width == 0 || sprite.height == 0 ||
+       sprite.data.len() < (sprite.width as usize) * (sprite.height as usize) {
+        return;
+    }
+    let e = extrude as i64;
+    for dy in -e..sprite.height as i64 + e {
+        let sy = dy.clamp(0, sprite.height as i64 - 1) as usize;
+        let row = &sprite.data[sy * sprite.width as usize..][..sprite.width as usize];
+        let py = (y as i64 + dy) as usize;
+        for dx in -e..sprite.width as i64 + e {
+            let sx = dx.clamp(0, sprite.width as i64 - 1) as usize;
+            let px = (x as i64 + dx) as usize;
+            page.data[py * page.width as usize + px] = row[sx];
+        }
+    }
+}
+
+/// Writes a JSON description of `atlas`: a frame for each of the `sprites`
+/// that was placed, with its rectangle in pixels and UVs, its size, name and
+/// index, and the page it's on; and the pages, whose images are called
+/// `page_names`.
+pub fn write_json<W: Write>(w: &mut W,
+                            atlas: &Atlas,
+                            sprites: &[Sprite],
+                            page_names: &[String],
+                            params: &AtlasParams)
+                            -> io::Result<()> {
+    writeln!(w, "{{")?;
+    write!(w, "  \"frames\": [")?;
+    let mut first = true;
+    for (sprite, placement) in sprites.iter().zip(&atlas.placements) {
+        let p = match *placement {
+            Some(p) => p,
+            None => continue,
+        };
+        let page = &atlas.pages[p.page];
+        let (pw, ph) = (page.width as f64, page.height as f64);
+        let (sw, sh) = (sprite.width, sprite.height);
+        write!(w, "{}\n    {{\"filename\": {}, \"index\": {}, \"page\": {}, \
+                   \"frame\": {{\"x\": {}, \"y\": {}, \"w\": {}, \"h\": {}}}, \
+                   \"rotated\": false, \"trimmed\": false, \
+                   \"spriteSourceSize\": {{\"x\": 0, \"y\": 0, \"w\": {}, \"h\": {}}}, \
+                   \"sourceSize\": {{\"w\": {}, \"h\": {}}}, \
+                   \"uv\": {{\"u0\": {}, \"v0\": {}, \"u1\": {}, \"v1\": {}}}}}",
+               if first { "" } else { "," },
+               json::string(sprite.name), sprite.index, p.page,
+               p.x, p.y, sw, sh,
+               sw, sh,
+               sw, sh,
+               p.x as f64 / pw, p.y as f64 / ph,
+               (p.x + sw) as f64 / pw, (p.y + sh) as f64 / ph)?;
+        first = false;
+    }
+    writeln!(w, "\n  ],")?;
+
+    writeln!(w, "  \"meta\": {{")?;
+    writeln!(w, "    \"app\": \"abrupng\",")?;
+    writeln!(w, "    \"version\": {},", json::string(env!("CARGO_PKG_VERSION")))?;
+    writeln!(w, "    \"padding\": {},", params.padding)?;
+    writeln!(w, "    \"extrude\": {},", params.extrude)?;
+    write!(w, "    \"pages\": [")?;
+    for (i, (page, name)) in atlas.pages.iter().zip(page_names).enumerate() {
+        write!(w, "{}\n      {{\"image\": {}, \"size\": {{\"w\": {}, \"h\": {}}}}}",
+               if i == 0 { "" } else { "," },
+               json::string(name), page.width, page.height)?;
+    }
+    writeln!(w, "\n    ]")?;
+    writeln!(w, "  }}")?;
+    writeln!(w, "}}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sprites of assorted sizes, each filled with its index plus one.
+    fn sprite_data(sizes: &[(u32, u32)]) -> Vec<Vec<u8>> {
+        sizes.iter()
+            .enumerate()
+            .map(|(i, &(w, h))| vec![(i % 255) as u8 + 1; (w * h) as usize])
+            .collect()
+    }
+
+    fn sprites<'a>(sizes: &[(u32, u32)], data: &'a [Vec<u8>]) -> Vec<Sprite<'a>> {
+        sizes.iter()
+            .zip(data)
+            .enumerate()
+            .map(|(index, (&(width, height), data))| {
+                Sprite { data, width, height, name: "", index }
+            })
+            .collect()
+    }
+
+    /// Sizes from a little xorshift generator, so they're varied but the
+    /// same every run.
+    fn assorted_sizes(count: usize, max: u32) -> Vec<(u32, u32)> {
+        let mut seed = 0x9e37_79b9u32;
+        let mut next = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed % max + 1
+        };
+        (0..count).map(|_| (next(), next())).collect()
+    }
+
+    /// Checks where everything went: every sprite that fits was placed, the
+    /// extruded tips are on their pages and at least `padding` apart, and the
+    /// pages are powers of two no bigger than `max_size`.
+    fn check(sizes: &[(u32, u32)], params: &AtlasParams) -> Atlas {
+        let data = sprite_data(sizes);
+        let atlas = pack(&sprites(sizes, &data), params);
+        let e = params.extrude;
+
+        for page in &atlas.pages {
+            assert!(page.width.is_power_of_two() && page.height.is_power_of_two());
+            assert!(page.width <= params.max_size && page.height <= params.max_size);
+        }
+
+        // (page, left, top, right, bottom) of each extruded tip.
+        let mut rects = vec![];
+        for (&(w, h), placement) in sizes.iter().zip(&atlas.placements) {
+            let fits = w + 2 * e <= params.max_size && h + 2 * e <= params.max_size;
+            let p = match *placement {
+                Some(p) => p,
+                None => {
+                    assert!(!fits, "{}x{} fits but wasn't placed", w, h);
+                    continue;
+                }
+            };
+            assert!(fits);
+            let page = &atlas.pages[p.page];
+            assert!(p.x >= e && p.y >= e);
+            assert!(p.x + w + e <= page.width && p.y + h + e <= page.height);
+            rects.push((p.page, p.x - e, p.y - e, p.x + w + e, p.y + h + e));
+        }
+
+        let pad = params.padding;
+        for (i, a) in rects.iter().enumerate() {
+            for b in &rects[i + 1..] {
+                let apart = a.0 != b.0 ||
+                            a.3 + pad <= b.1 || b.3 + pad <= a.1 ||
+                            a.4 + pad <= b.2 || b.4 + pad <= a.2;
+                assert!(apart, "{:?} and {:?} are closer than {}", a, b, pad);
+            }
+        }
+        atlas
+    }
+
+    #[test]
+    fn no_overlaps() {
+        let sizes = assorted_sizes(300, 40);
+        for &(padding, extrude) in &[(0, 0), (2, 1), (5, 3)] {
+            let params = AtlasParams { max_size: 256, padding, extrude };
+            let atlas = check(&sizes, &params);
+            assert!(atlas.pages.len() > 1);
+            assert!(atlas.placements.iter().all(|p| p.is_some()));
+        }
+    }
+
+    #[test]
+    fn oversized_sprites_are_left_out() {
+        let params = AtlasParams { max_size: 64, padding: 2, extrude: 1 };
+        let sizes = [(10, 10), (63, 5), (62, 62), (65, 1), (1, 200), (30, 20)];
+        let atlas = check(&sizes, &params);
+        let placed = atlas.placements.iter().map(|p| p.is_some()).collect::<Vec<_>>();
+        assert_eq!(placed, [true, false, true, false, false, true]);
+    }
+
+    #[test]
+    fn pages_shrink_to_a_power_of_two() {
+        let params = AtlasParams { max_size: 1024, padding: 2, extrude: 1 };
+        let atlas = check(&[(30, 10), (20, 40)], &params);
+        assert_eq!(atlas.pages.len(), 1);
+        assert_eq!((atlas.pages[0].width, atlas.pages[0].height), (64, 64));
+    }
+
+    #[test]
+    fn tips_are_extruded() {
+        let params = AtlasParams { max_size: 64, padding: 0, extrude: 2 };
+        let data = [1, 2, 3, 4, 5, 6];
+        let sprite = Sprite { data: &data, width: 3, height: 2, name: "", index: 0 };
+        let atlas = pack(&[sprite], &params);
+        let page = &atlas.pages[0];
+        assert_eq!((page.width, page.height), (8, 8));
+        let rows = page.data.chunks(8).take(6).collect::<Vec<_>>();
+        assert_eq!(rows[0], [1, 1, 1, 2, 3, 3, 3, 0]);
+        assert_eq!(rows[2], [1, 1, 1, 2, 3, 3, 3, 0]);
+        assert_eq!(rows[3], [4, 4, 4, 5, 6, 6, 6, 0]);
+        assert_eq!(rows[5], [4, 4, 4, 5, 6, 6, 6, 0]);
+    }
+}
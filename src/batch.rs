@@ -0,0 +1,214 @@
+//! Extracting from many ABR files at once.
+
+use cli::BatchOptions;
+use err::Error;
+use glob;
+use rayon::prelude::*;
+use rayon::ThreadPoolBuilder;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// How extracting one file went.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Summary {
+    /// Number of brushes in the file.
+    pub brushes: usize,
+    /// Number of those that couldn't be read or written.
+    pub failed: usize,
+}
+
+/// Whether `inputs` names just one file, which is extracted the way it was
+/// before abrupng took several inputs.
+pub fn is_single_file(inputs: &[String]) -> bool {
+    inputs.len() == 1 && !is_pattern(&inputs[0]) && !Path::new(&inputs[0]).is_dir()
+}
+
+/// Turns the inputs given on the command-line into a list of ABR files.
+/// Directories are searched (recursively) for files ending in `.abr`, and
+/// glob patterns are expanded. Each file is only listed once.
+pub fn expand_inputs(inputs: &[String]) -> Result<Vec<PathBuf>, Error> {
+    let mut files = vec![];
+    for input in inputs {
+        if is_pattern(input) {
+            let paths = glob::glob(input)
+                .map_err(|e| Error::BadInputPattern { pattern: input.clone(), err: e })?;
+            for path_result in paths {
+                match path_result {
+                    Ok(path) => add_path(path, &mut files)?,
+                    Err(e) => eprintln!("warning: skipping {}: {}", e.path().display(), e),
+                }
+            }
+        } else {
+            add_path(PathBuf::from(input), &mut files)?;
+        }
+    }
+
+    let mut seen = HashSet::new();
+    files.retain(|path| seen.insert(path.clone()));
+
+    if files.is_empty() {
+        return Err(Error::NoAbrsFound);
+    }
+    Ok(files)
+}
+
+fn is_pattern(input: &str) -> bool {
+    input.contains(['*', '?', '['])
+}
+
+/// Adds `path` to `files`, or the ABR files in it if it's a directory.
+fn add_path(path: PathBuf, files: &mut Vec<PathBuf>) -> Result<(), Error> {
+    if !path.is_dir() {
+        files.push(path);
+        return Ok(());
+    }
+
+    let entries = fs::read_dir(&path)
+        .and_then(|read_dir| read_dir.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| Error::CouldntReadDir { dir_path: path.clone(), err: e });
+    let mut entries = entries?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let entry_path = entry.path();
+        // Don't follow symlinks to directories, which could loop.
+        let is_dir = entry.file_type().map(|t| t.is_dir()).unwrap_or(false);
+        let is_abr = entry_path.extension()
+            .map(|ext| ext.eq_ignore_ascii_case("abr"))
+            .unwrap_or(false);
+        if is_dir {
+            add_path(entry_path, files)?;
+        } else if is_abr {
+            files.push(entry_path);
+        }
+    }
+    Ok(())
+}
+
+/// Extracts each of `inputs` into its own directory in `output_path`,
+/// several at a time, by calling `extract` with the input and output paths.
+/// Prints a summary afterwards, and fails if more files or brushes failed
+/// than `options` allows.
+pub fn extract_all<F>(inputs: &[PathBuf],
+                      output_path: &Path,
+                      options: &BatchOptions,
+                      extract: F)
+                      -> Result<(), Error>
+    where F: Fn(&Path, &Path) -> Result<Summary, Error> + Sync
+{
+    fs::create_dir_all(output_path)
+        .map_err(|e| Error::CouldntCreateOutputDir {
+            output_path: output_path.to_path_buf(),
+            err: e,
+        })?;
+    let output_dirs = output_dirs(inputs, output_path);
+
+    let pool = ThreadPoolBuilder::new().num_threads(options.jobs.unwrap_or(0)).build()?;
+    let results = pool.install(|| {
+        inputs.par_iter()
+            .zip(&output_dirs)
+            .map(|(input, output_dir)| {
+                let result = extract(input, output_dir);
+                if let Err(ref e) = result {
+                    eprintln!("error: {}: {}", input.display(), e);
+                }
+                result
+            })
+            .collect::<Vec<_>>()
+    });
+
+    let mut failed_files = vec![];
+    let mut total = Summary::default();
+    for (input, result) in inputs.iter().zip(&results) {
+        match *result {
+            Ok(summary) => {
+                total.brushes += summary.brushes;
+                total.failed += summary.failed;
+            }
+            Err(_) => failed_files.push(input),
+        }
+    }
+
+    println!();
+    println!("Extracted {} of {} files: {} brushes, {} of which couldn't be read or written.",
+             inputs.len() - failed_files.len(),
+             inputs.len(),
+             total.brushes,
+             total.failed);
+    if !failed_files.is_empty() {
+        println!("Couldn't extract:");
+        for input in &failed_files {
+            println!("    {}", input.display());
+        }
+    }
+
+    if options.max_failed_files.exceeded(failed_files.len(), inputs.len()) {
+        return Err(Error::TooManyFailures {
+            what: "files",
+            failed: failed_files.len(),
+            total: inputs.len(),
+        });
+    }
+    check_brush_failures(options, &total)
+}
+
+/// Fails if more brushes couldn't be read or written than `options` allows.
+pub fn check_brush_failures(options: &BatchOptions, summary: &Summary) -> Result<(), Error> {
+    match options.max_failed_brushes {
+        Some(max) if max.exceeded(summary.failed, summary.brushes) => {
+            Err(Error::TooManyFailures {
+                what: "brushes",
+                failed: summary.failed,
+                total: summary.brushes,
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Picks an output directory in `output_path` for each input, named after
+/// the input's stem. Inputs with the same stem get a number on the end, in
+/// the order of their full paths, so the names don't depend on the order the
+/// inputs were given in.
+fn output_dirs(inputs: &[PathBuf], output_path: &Path) -> Vec<PathBuf> {
+    let mut order = (0..inputs.len()).collect::<Vec<_>>();
+    order.sort_by(|&a, &b| inputs[a].cmp(&inputs[b]));
+
+    let mut used = HashSet::new();
+    let mut dirs = vec![PathBuf::new(); inputs.len()];
+    for idx in order {
+        let stem = inputs[idx].file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "brushes".to_string());
+        let mut name = stem.clone();
+        let mut n = 1;
+        while !used.insert(name.to_lowercase()) {
+            n += 1;
+            name = format!("{}-{}", stem, n);
+        }
+        dirs[idx] = output_path.join(name);
+    }
+    dirs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_dirs_dont_depend_on_input_order() {
+        let out = Path::new("out");
+        let inputs = ["b/x.abr", "a/X.abr", "c/y.abr", "a/x.abr"]
+            .iter()
+            .map(PathBuf::from)
+            .collect::<Vec<_>>();
+        let dirs = output_dirs(&inputs, out);
+        assert_eq!(dirs, ["x-3", "X", "y", "x-2"].iter().map(|d| out.join(d)).collect::<Vec<_>>());
+
+        let reversed = inputs.iter().rev().cloned().collect::<Vec<_>>();
+        let mut reversed_dirs = output_dirs(&reversed, out);
+        reversed_dirs.reverse();
+        assert_eq!(reversed_dirs, dirs);
+    }
+}
@@ -1,54 +1,745 @@
+use abrupng::{abr, atlas, gih, png, preview};
 use err::Error;
-use getopts::Options;
+use getopts::{Matches, Options};
 use std::env;
 use std::path::PathBuf;
+use template::Template;
 
 pub enum Command {
-    Help,
-    Process {
+    /// Print this usage text.
+    Help(String),
+    /// Write the brushes out as files.
+    Extract {
+        /// ABR files, directories of them, or glob patterns.
+        inputs: Vec<String>,
+        output_path: Option<PathBuf>,
+        options: ExtractOptions,
+        batch: BatchOptions,
+    },
+    /// Print a line about each brush.
+    List {
+        input_path: PathBuf,
+        open_options: abr::OpenOptions,
+        /// Memory-map the file.
+        mmap: bool,
+        /// Print JSON instead of a table.
+        json: bool,
+        /// Which brushes to list. `None` means all of them.
+        only: Option<Selection>,
+    },
+    /// Print the file's version and what it's made of.
+    Info {
+        input_path: PathBuf,
+        open_options: abr::OpenOptions,
+        /// Memory-map the file.
+        mmap: bool,
+    },
+    /// Draw all the brushes on one contact sheet.
+    Preview {
         input_path: PathBuf,
         output_path: PathBuf,
+        open_options: abr::OpenOptions,
+        /// Memory-map the file.
+        mmap: bool,
+        params: preview::SheetParams,
     },
+    /// Build an ABR out of the images in a directory.
+    Png2Abr {
+        input_dir: PathBuf,
+        output_path: PathBuf,
+        options: Png2AbrOptions,
+    },
+}
+
+/// Which of the commands that write brushes out is being run. They take
+/// different options.
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum ExtractKind {
+    /// No command: any format, like before there were commands.
+    Default,
+    /// `extract`: PNGs.
+    Extract,
+    /// `convert`: other brush formats.
+    Convert,
+}
+
+/// Settings for how brushes are extracted.
+pub struct ExtractOptions {
+    /// Convert 16-bit brushes to 8-bit before saving.
+    pub eight_bit: bool,
+    /// Try to read unknown ABR versions anyway.
+    pub guess_format: bool,
+    /// Memory-map the input files.
+    pub mmap: bool,
+    /// What kind of file to write each brush as.
+    pub format: OutputFormat,
+    /// Settings for the image pipe, with the gih format.
+    pub pipe: gih::PipeParams,
+    /// Settings for packing the atlas, with the atlas format.
+    pub atlas: atlas::AtlasParams,
+    /// How brushes are written as PNGs.
+    pub png_style: png::MaskStyle,
+    /// Invert brushes written as PNGs.
+    pub invert: bool,
+    /// Also write a manifest.json describing the brushes.
+    pub manifest: bool,
+    /// What to do when the output directory already exists.
+    pub existing: ExistingPolicy,
+    /// How to name the file each brush is written to.
+    pub filename: Template,
+    /// Which brushes to extract. `None` means all of them.
+    pub only: Option<Selection>,
+}
+
+/// A set of brush indices, like `3,7,10-20`.
+#[derive(Debug, Clone)]
+pub struct Selection {
+    /// Ranges of indices, including both ends.
+    ranges: Vec<(usize, usize)>,
+}
+
+impl Selection {
+    /// Whether the brush at `idx` is picked.
+    pub fn contains(&self, idx: usize) -> bool {
+        self.ranges.iter().any(|&(start, end)| start <= idx && idx <= end)
+    }
+
+    /// The highest index picked.
+    pub fn max(&self) -> usize {
+        self.ranges.iter().map(|&(_, end)| end).max().unwrap_or(0)
+    }
 }
 
-pub fn make_options() -> Options {
+/// What to do about an output directory that already exists. Whatever the
+/// policy, files abrupng didn't write are never overwritten.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum ExistingPolicy {
+    /// Fail; the directory must be new.
+    Fail,
+    /// Overwrite files from earlier runs, and remove any that weren't
+    /// written this time.
+    Force,
+    /// Leave files that are already there alone, only writing new ones.
+    SkipExisting,
+    /// Overwrite files from earlier runs, leaving any that weren't written
+    /// this time.
+    Merge,
+}
+
+/// Settings for extracting from many ABR files at once.
+pub struct BatchOptions {
+    /// How many files to work on at once. `None` means one per CPU.
+    pub jobs: Option<usize>,
+    /// Fail if more files than this can't be extracted.
+    pub max_failed_files: Threshold,
+    /// Fail if more brushes than this can't be read or written. `None`
+    /// means any number is fine.
+    pub max_failed_brushes: Option<Threshold>,
+}
+
+/// A limit on how many of something can fail.
+#[derive(Copy, Clone)]
+pub enum Threshold {
+    /// At most this many.
+    Count(usize),
+    /// At most this percentage of them.
+    Percent(f64),
+}
+
+impl Threshold {
+    /// Whether `failed` out of `total` is over the limit.
+    pub fn exceeded(self, failed: usize, total: usize) -> bool {
+        match self {
+            Threshold::Count(max) => failed > max,
+            Threshold::Percent(max) => failed as f64 > total as f64 * max / 100.0,
+        }
+    }
+}
+
+/// Settings for how images are turned into brushes by png2abr.
+pub struct Png2AbrOptions {
+    /// Where the mask comes from. `None` means the alpha channel if there is
+    /// one, otherwise the luminance.
+    pub mask: Option<png::MaskSource>,
+    /// Flip the mask.
+    pub invert: bool,
+    /// Spacing to give every brush.
+    pub spacing: u16,
+}
+
+#[derive(Copy, Clone)]
+pub enum OutputFormat {
+    Png,
+    Gbr,
+    /// All the brushes in one GIMP image pipe.
+    Gih,
+    /// All the brushes in one Krita resource bundle.
+    Bundle,
+    /// All the brushes in one MyPaint brush pack.
+    MyPaint,
+    /// All the brushes packed into texture atlas PNGs, with a JSON file
+    /// saying where they are.
+    Atlas,
+}
+
+impl OutputFormat {
+    /// File extension for files of this format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Png => "png",
+            OutputFormat::Gbr => "gbr",
+            OutputFormat::Gih => "gih",
+            OutputFormat::Bundle => "bundle",
+            OutputFormat::MyPaint => "zip",
+            OutputFormat::Atlas => "png",
+        }
+    }
+}
+
+fn make_extract_options(kind: ExtractKind) -> Options {
     let mut opts = Options::new();
-    opts.optopt("o", "", "set output directory (will be created)", "DIR");
+    opts.optopt("o", "", "set output directory (will be created); with several \
+                          inputs, each gets a directory in here", "DIR");
+    match kind {
+        ExtractKind::Default => {
+            opts.optopt("f", "format", "output format: png (default), gbr (GIMP brush), \
+                                       gih (all brushes in one GIMP image pipe), bundle \
+                                       (all brushes in one Krita resource bundle), \
+                                       mypaint (all brushes in one MyPaint brush pack), \
+                                       or atlas (texture atlas PNGs plus JSON)",
+                        "FORMAT");
+        }
+        ExtractKind::Convert => {
+            opts.reqopt("f", "format", "output format: gbr (GIMP brush), gih (all brushes \
+                                       in one GIMP image pipe), bundle (all brushes in one \
+                                       Krita resource bundle), mypaint (all brushes in \
+                                       one MyPaint brush pack), or atlas (texture atlas \
+                                       PNGs plus JSON)",
+                        "FORMAT");
+        }
+        ExtractKind::Extract => (),
+    }
+    if kind != ExtractKind::Extract {
+        opts.optopt("", "gih-selection", "how the image pipe picks brushes: random \
+                                          (default), incremental, or angular", "MODE");
+        opts.optopt("", "gih-grid", "cells per layer when GIMP opens the image pipe \
+                                     (default 1x1)", "COLSxROWS");
+        opts.optopt("", "atlas-size", "largest width and height of an atlas page, a power \
+                                       of two (default 2048)", "PIXELS");
+        opts.optopt("", "atlas-padding", "empty pixels between brushes in an atlas \
+                                          (default 2)", "PIXELS");
+        opts.optopt("", "atlas-extrude", "pixels to repeat each brush's edges outwards by \
+                                          in an atlas (default 1)", "PIXELS");
+    }
+    opts.optflag("", "alpha", "save PNGs (and atlases) with the brush as the alpha \
+                               channel, instead of as white on black");
+    opts.optopt("", "fill", "colour of the brush with --alpha, as a name (black or \
+                             white) or hex RRGGBB (default black; implies --alpha)",
+                "COLOUR");
+    opts.optflag("", "invert", "invert PNGs and atlases (eg. black on white instead of \
+                                white on black)");
+    if kind != ExtractKind::Convert {
+        opts.optflag("", "8bit", "save 16-bit brushes as 8-bit PNGs");
+    }
+    opts.optopt("", "filename", "name each brush's file from a template, with {index} \
+                                 (or eg. {index:04} for 0001), {name}, {uuid}, {width}, \
+                                 {height} and {stem} (the ABR's name); default {index}",
+                "TEMPLATE");
+    opts.optopt("", "only", "only extract the brushes at these indices, eg. 3,7,10-20",
+                "LIST");
+    opts.optflag("", "manifest", "also write manifest.json, describing each brush and \
+                                  the file it went in");
+    opts.optflag("", "force", "extract into the output directory even if it exists, \
+                               replacing what abrupng wrote there before");
+    opts.optflag("", "skip-existing", "extract into the output directory even if it \
+                                       exists, leaving files already there alone");
+    opts.optflag("", "merge", "extract into the output directory even if it exists, \
+                               overwriting what abrupng wrote there before but keeping \
+                               files it doesn't write this time");
+    opts.optopt("j", "jobs", "with several inputs, how many to extract at once \
+                              (default: one per CPU)", "N");
+    opts.optopt("", "max-failed-files", "with several inputs, exit with an error if more \
+                                         than this many (or this percentage, eg. 5%) \
+                                         couldn't be extracted (default 0)", "N");
+    opts.optopt("", "max-failed-brushes", "exit with an error if more than this many (or \
+                                           this percentage) of the brushes couldn't be \
+                                           read or written (default: no limit)", "N");
+    opts.optflag("", "guess-format", "try to read unknown ABR versions as ABR6");
+    opts.optflag("", "mmap", "memory-map the input instead of reading it (abrupng \
+                              crashes if the file is cut short while it's mapped)");
+    opts.optflag("h", "help", "print this help menu");
+    opts
+}
+
+/// Options for commands that just read an ABR.
+fn make_read_options() -> Options {
+    let mut opts = Options::new();
+    opts.optflag("", "guess-format", "try to read unknown ABR versions as ABR6");
+    opts.optflag("", "mmap", "memory-map the input instead of reading it (abrupng \
+                              crashes if the file is cut short while it's mapped)");
+    opts.optflag("h", "help", "print this help menu");
+    opts
+}
+
+fn make_list_options() -> Options {
+    let mut opts = make_read_options();
+    opts.optflag("", "json", "print the list as JSON, in the same form as the manifest \
+                              extract --manifest writes");
+    opts.optopt("", "only", "only list the brushes at these indices, eg. 3,7,10-20", "LIST");
+    opts
+}
+
+fn make_preview_options() -> Options {
+    let mut opts = Options::new();
+    opts.optopt("o", "", "set output file (default: INPUT-preview.png)", "FILE");
+    opts.optopt("", "cell-size", "width and height of each brush's cell, in pixels \
+                                  (default 128)", "PIXELS");
+    opts.optopt("", "columns", "number of cells across (default: enough to make the \
+                                sheet roughly square)", "N");
+    opts.optflag("", "no-labels", "don't label the cells with the brushes' indices");
+    opts.optflag("", "guess-format", "try to read unknown ABR versions as ABR6");
+    opts.optflag("", "mmap", "memory-map the input instead of reading it (abrupng \
+                              crashes if the file is cut short while it's mapped)");
     opts.optflag("h", "help", "print this help menu");
     opts
 }
 
-pub fn print_usage(opts: &Options) {
+fn make_png2abr_options() -> Options {
+    let mut opts = Options::new();
+    opts.optopt("o", "", "set output file (default: INPUT_DIR.abr)", "FILE");
+    opts.optopt("", "mask", "what becomes the brush: alpha or luminance (default: \
+                             alpha if the image has it, otherwise luminance)", "SOURCE");
+    opts.optflag("", "invert", "invert the brush (eg. for dark ink on a light background)");
+    opts.optopt("", "spacing", "spacing, as a percentage of the brush size \
+                                (default 25)", "PERCENT");
+    opts.optflag("h", "help", "print this help menu");
+    opts
+}
+
+fn usage(opts: &Options) -> String {
     let brief = "Extracts image brushes from Adobe ABR files as PNGs.\n\nUsage:\n    abrupng \
-                 INPUT [-o OUTPUT]";
-    print!("{}", opts.usage(&brief));
+                 INPUT... [-o OUTPUT] [-f FORMAT] [options]\n    abrupng COMMAND ...\n\n\
+                 Each INPUT can be an ABR file, a directory to search for them, or a glob \
+                 pattern like \"brushes/*.abr\".\n\n\
+                 Commands (see abrupng COMMAND -h):\n    \
+                 extract   save the brushes as PNGs\n    \
+                 convert   save the brushes in another brush format\n    \
+                 list      list the brushes, without saving anything\n    \
+                 info      show the file's version and what's in it\n    \
+                 preview   draw all the brushes on one contact sheet\n    \
+                 png2abr   build an ABR from a directory of PNGs\n\n\
+                 With no command, abrupng extracts in whatever format -f says.";
+    opts.usage(brief)
+}
+
+fn extract_usage(opts: &Options) -> String {
+    let brief = "Extracts the brushes in an ABR file as PNGs.\n\nUsage:\n    abrupng extract \
+                 INPUT... [-o OUTPUT] [options]";
+    opts.usage(brief)
+}
+
+fn convert_usage(opts: &Options) -> String {
+    let brief = "Converts the brushes in an ABR file to another brush format.\n\nUsage:\n    \
+                 abrupng convert INPUT... -f FORMAT [-o OUTPUT] [options]";
+    opts.usage(brief)
+}
+
+fn list_usage(opts: &Options) -> String {
+    let brief = "Lists the brushes in an ABR file: index, byte offset, size, bit-depth, \
+                 compression and name.\n\nUsage:\n    abrupng list INPUT [options]";
+    opts.usage(brief)
+}
+
+fn info_usage(opts: &Options) -> String {
+    let brief = "Shows an ABR file's version and the blocks it's made of.\n\nUsage:\n    \
+                 abrupng info INPUT [options]";
+    opts.usage(brief)
 }
 
-pub fn parse_cli_options(opts: &Options) -> Result<Command, Error> {
+fn preview_usage(opts: &Options) -> String {
+    let brief = "Draws every brush in an ABR file on one PNG contact sheet, labelled with \
+                 its index. Brushes that couldn't be read are crossed out.\n\nUsage:\n    \
+                 abrupng preview INPUT [-o OUTPUT] [options]";
+    opts.usage(brief)
+}
+
+fn png2abr_usage(opts: &Options) -> String {
+    let brief = "Builds an Adobe ABR file from a directory of PNGs, one brush per \
+                 image.\n\nUsage:\n    abrupng png2abr INPUT_DIR [-o OUTPUT] [options]";
+    opts.usage(brief)
+}
+
+pub fn parse_cli_options() -> Result<Command, Error> {
     let args: Vec<String> = env::args().collect();
-    let matches = opts.parse(&args[1..])?;
+    match args.get(1).map(|s| &s[..]) {
+        Some("extract") => parse_extract_options(&args[2..], ExtractKind::Extract),
+        Some("convert") => parse_extract_options(&args[2..], ExtractKind::Convert),
+        Some("list") => {
+            parse_read_options(&args[2..], make_list_options(), list_usage,
+                               |input_path, open_options, matches| {
+                Ok(Command::List {
+                    input_path,
+                    open_options,
+                    mmap: matches.opt_present("mmap"),
+                    json: matches.opt_present("json"),
+                    only: parse_only(matches)?,
+                })
+            })
+        }
+        Some("info") => {
+            parse_read_options(&args[2..], make_read_options(), info_usage,
+                               |input_path, open_options, matches| {
+                Ok(Command::Info {
+                    input_path,
+                    open_options,
+                    mmap: matches.opt_present("mmap"),
+                })
+            })
+        }
+        Some("preview") => parse_preview_options(&args[2..]),
+        Some("png2abr") => parse_png2abr_options(&args[2..]),
+        _ => parse_extract_options(&args[1..], ExtractKind::Default),
+    }
+}
 
-    if matches.opt_present("h") {
-        Ok(Command::Help)
+fn parse_extract_options(args: &[String], kind: ExtractKind) -> Result<Command, Error> {
+    let opts = make_extract_options(kind);
+
+    // Check for -h first, since convert's required -f would stop the parse.
+    if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        return Ok(Command::Help(match kind {
+            ExtractKind::Default => usage(&opts),
+            ExtractKind::Extract => extract_usage(&opts),
+            ExtractKind::Convert => convert_usage(&opts),
+        }));
+    }
+
+    let matches = opts.parse(args)?;
+    if matches.free.is_empty() {
+        return Err(Error::NoInputFiles);
+    }
+    let inputs = matches.free.clone();
+    let output_path = matches.opt_str("o").map(PathBuf::from);
+
+    let format = if kind == ExtractKind::Extract {
+        OutputFormat::Png
     } else {
-        let input_path = if matches.free.len() == 1 {
-            PathBuf::from(&matches.free[0])
-        } else {
-            return Err(Error::WrongNumberOfInputFiles(matches.free.len()));
-        };
+        match matches.opt_str("f").as_ref().map(|s| &s[..]) {
+            None => OutputFormat::Png,
+            Some("png") if kind == ExtractKind::Default => OutputFormat::Png,
+            Some("gbr") => OutputFormat::Gbr,
+            Some("gih") => OutputFormat::Gih,
+            Some("bundle") => OutputFormat::Bundle,
+            Some("mypaint") => OutputFormat::MyPaint,
+            Some("atlas") => OutputFormat::Atlas,
+            Some(s) => return Err(Error::UnknownOutputFormat(s.to_string())),
+        }
+    };
 
-        // Get the output directory's path. If one isn't given, try to guess one
-        // from the stem of the input file (ex. mybrushes.abr => ./mybrushes).
-        let output_path = match matches.opt_str("o") {
-            Some(s) => PathBuf::from(s),
-            None => {
-                match input_path.file_stem() {
-                    Some(stem) => PathBuf::from(stem),
-                    None => return Err(Error::CouldntGuessOutputName),
+    let mut pipe = gih::PipeParams::default();
+    if kind != ExtractKind::Extract {
+        match matches.opt_str("gih-selection").as_ref().map(|s| &s[..]) {
+            None => (),
+            Some("random") => pipe.selection = gih::Selection::Random,
+            Some("incremental") => pipe.selection = gih::Selection::Incremental,
+            Some("angular") => pipe.selection = gih::Selection::Angular,
+            Some(s) => return Err(Error::BadOptionValue("gih-selection", s.to_string())),
+        }
+        if let Some(s) = matches.opt_str("gih-grid") {
+            match parse_grid(&s) {
+                Some((cols, rows)) => { pipe.cols = cols; pipe.rows = rows; }
+                None => return Err(Error::BadOptionValue("gih-grid", s)),
+            }
+        }
+    }
+
+    let mut atlas = atlas::AtlasParams::default();
+    if kind != ExtractKind::Extract {
+        if let Some(s) = matches.opt_str("atlas-size") {
+            match s.parse::<u32>() {
+                Ok(size) if size.is_power_of_two() && (64..=16384).contains(&size) => {
+                    atlas.max_size = size
                 }
+                _ => return Err(Error::BadOptionValue("atlas-size", s)),
+            }
+        }
+        if let Some(s) = matches.opt_str("atlas-padding") {
+            match s.parse() {
+                Ok(padding) if padding <= 64 => atlas.padding = padding,
+                _ => return Err(Error::BadOptionValue("atlas-padding", s)),
+            }
+        }
+        if let Some(s) = matches.opt_str("atlas-extrude") {
+            match s.parse() {
+                Ok(extrude) if extrude <= 64 => atlas.extrude = extrude,
+                _ => return Err(Error::BadOptionValue("atlas-extrude", s)),
+            }
+        }
+    }
+
+    let fill = match matches.opt_str("fill") {
+        None => None,
+        Some(s) => match parse_colour(&s) {
+            Some(fill) => Some(fill),
+            None => return Err(Error::BadOptionValue("fill", s)),
+        },
+    };
+    let png_style = if fill.is_some() || matches.opt_present("alpha") {
+        png::MaskStyle::Alpha { fill: fill.unwrap_or([0, 0, 0]) }
+    } else {
+        png::MaskStyle::Greyscale
+    };
+    let eight_bit = kind != ExtractKind::Convert && matches.opt_present("8bit");
+
+    let options = ExtractOptions {
+        eight_bit,
+        guess_format: matches.opt_present("guess-format"),
+        mmap: matches.opt_present("mmap"),
+        format,
+        pipe,
+        atlas,
+        png_style,
+        invert: matches.opt_present("invert"),
+        manifest: matches.opt_present("manifest"),
+        existing: parse_existing_policy(&matches)?,
+        filename: match matches.opt_str("filename") {
+            Some(s) => Template::parse(&s)?,
+            None => Template::default(),
+        },
+        only: parse_only(&matches)?,
+    };
+
+    let jobs = match matches.opt_str("jobs") {
+        None => None,
+        Some(s) => match s.parse() {
+            Ok(jobs) if jobs > 0 => Some(jobs),
+            _ => return Err(Error::BadOptionValue("jobs", s)),
+        },
+    };
+    let max_failed_files = match matches.opt_str("max-failed-files") {
+        None => Threshold::Count(0),
+        Some(s) => match parse_threshold(&s) {
+            Some(threshold) => threshold,
+            None => return Err(Error::BadOptionValue("max-failed-files", s)),
+        },
+    };
+    let max_failed_brushes = match matches.opt_str("max-failed-brushes") {
+        None => None,
+        Some(s) => match parse_threshold(&s) {
+            Some(threshold) => Some(threshold),
+            None => return Err(Error::BadOptionValue("max-failed-brushes", s)),
+        },
+    };
+    let batch = BatchOptions { jobs, max_failed_files, max_failed_brushes };
+
+    Ok(Command::Extract { inputs, output_path, options, batch })
+}
+
+/// Parses the options of a command that just reads an ABR, making the
+/// command with `make_command`.
+fn parse_read_options<F>(args: &[String],
+                         opts: Options,
+                         usage: fn(&Options) -> String,
+                         make_command: F)
+                         -> Result<Command, Error>
+    where F: FnOnce(PathBuf, abr::OpenOptions, &Matches) -> Result<Command, Error>
+{
+    let matches = opts.parse(args)?;
+
+    if matches.opt_present("h") {
+        return Ok(Command::Help(usage(&opts)));
+    }
+
+    let input_path = if matches.free.len() == 1 {
+        PathBuf::from(&matches.free[0])
+    } else {
+        return Err(Error::WrongNumberOfInputFiles(matches.free.len()));
+    };
+
+    let open_options = abr::OpenOptions {
+        guess_unknown_versions: matches.opt_present("guess-format"),
+    };
+
+    make_command(input_path, open_options, &matches)
+}
+
+fn parse_preview_options(args: &[String]) -> Result<Command, Error> {
+    let opts = make_preview_options();
+    let matches = opts.parse(args)?;
+
+    if matches.opt_present("h") {
+        return Ok(Command::Help(preview_usage(&opts)));
+    }
+
+    let input_path = if matches.free.len() == 1 {
+        PathBuf::from(&matches.free[0])
+    } else {
+        return Err(Error::WrongNumberOfInputFiles(matches.free.len()));
+    };
+
+    // Name the sheet after the ABR (ex. mybrushes.abr => ./mybrushes-preview.png).
+    let output_path = match matches.opt_str("o") {
+        Some(s) => PathBuf::from(s),
+        None => {
+            match input_path.file_stem() {
+                Some(stem) => PathBuf::from(format!("{}-preview.png", stem.to_string_lossy())),
+                None => return Err(Error::CouldntGuessOutputName),
             }
+        }
+    };
+
+    let mut params = preview::SheetParams::default();
+    if let Some(s) = matches.opt_str("cell-size") {
+        match s.parse() {
+            Ok(size) if (8..=4096).contains(&size) => params.cell_size = size,
+            _ => return Err(Error::BadOptionValue("cell-size", s)),
+        }
+    }
+    if let Some(s) = matches.opt_str("columns") {
+        match s.parse() {
+            Ok(columns) if columns > 0 => params.columns = Some(columns),
+            _ => return Err(Error::BadOptionValue("columns", s)),
+        }
+    }
+    params.labels = !matches.opt_present("no-labels");
+
+    let open_options = abr::OpenOptions {
+        guess_unknown_versions: matches.opt_present("guess-format"),
+    };
+
+    let mmap = matches.opt_present("mmap");
+
+    Ok(Command::Preview { input_path, output_path, open_options, mmap, params })
+}
+
+fn parse_png2abr_options(args: &[String]) -> Result<Command, Error> {
+    let opts = make_png2abr_options();
+    let matches = opts.parse(args)?;
+
+    if matches.opt_present("h") {
+        return Ok(Command::Help(png2abr_usage(&opts)));
+    }
+
+    let input_dir = if matches.free.len() == 1 {
+        PathBuf::from(&matches.free[0])
+    } else {
+        return Err(Error::WrongNumberOfInputFiles(matches.free.len()));
+    };
+
+    // Name the ABR after the directory (ex. tips/ => ./tips.abr).
+    let output_path = match matches.opt_str("o") {
+        Some(s) => PathBuf::from(s),
+        None => {
+            match input_dir.file_name() {
+                Some(name) => PathBuf::from(format!("{}.abr", name.to_string_lossy())),
+                None => return Err(Error::CouldntGuessOutputName),
+            }
+        }
+    };
+
+    let mask = match matches.opt_str("mask").as_ref().map(|s| &s[..]) {
+        None => None,
+        Some("alpha") => Some(png::MaskSource::Alpha),
+        Some("luminance") => Some(png::MaskSource::Luminance),
+        Some(s) => return Err(Error::BadOptionValue("mask", s.to_string())),
+    };
+
+    let spacing = match matches.opt_str("spacing") {
+        None => 25,
+        Some(s) => match s.parse() {
+            Ok(spacing) if spacing > 0 => spacing,
+            _ => return Err(Error::BadOptionValue("spacing", s)),
+        },
+    };
+
+    let options = Png2AbrOptions {
+        mask,
+        invert: matches.opt_present("invert"),
+        spacing,
+    };
+
+    Ok(Command::Png2Abr { input_dir, output_path, options })
+}
+
+/// Parses a colour like `black` or `ff8000` (optionally with a leading `#`).
+fn parse_colour(s: &str) -> Option<[u8; 3]> {
+    match s {
+        "black" => return Some([0, 0, 0]),
+        "white" => return Some([255, 255, 255]),
+        _ => (),
+    }
+    let hex = s.trim_start_matches('#');
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    Some([channel(0)?, channel(2)?, channel(4)?])
+}
+
+/// Gets the policy for existing output directories from the --force,
+/// --skip-existing and --merge flags, only one of which may be given.
+fn parse_existing_policy(matches: &Matches) -> Result<ExistingPolicy, Error> {
+    let flags = [
+        ("force", ExistingPolicy::Force),
+        ("skip-existing", ExistingPolicy::SkipExisting),
+        ("merge", ExistingPolicy::Merge),
+    ];
+    let mut given = flags.iter().filter(|(name, _)| matches.opt_present(name));
+    match (given.next(), given.next()) {
+        (None, _) => Ok(ExistingPolicy::Fail),
+        (Some(&(_, policy)), None) => Ok(policy),
+        (Some(&(a, _)), Some(&(b, _))) => Err(Error::ConflictingOptions(a, b)),
+    }
+}
+
+/// Parses a failure threshold like `10` or `5%`.
+fn parse_threshold(s: &str) -> Option<Threshold> {
+    if let Some(percent) = s.strip_suffix('%') {
+        match percent.parse::<f64>() {
+            Ok(percent) if (0.0..=100.0).contains(&percent) => Some(Threshold::Percent(percent)),
+            _ => None,
+        }
+    } else {
+        s.parse().ok().map(Threshold::Count)
+    }
+}
+
+/// Parses the `--only` option, if it was given.
+fn parse_only(matches: &Matches) -> Result<Option<Selection>, Error> {
+    match matches.opt_str("only") {
+        None => Ok(None),
+        Some(s) => match parse_selection(&s) {
+            Some(selection) => Ok(Some(selection)),
+            None => Err(Error::BadOptionValue("only", s)),
+        },
+    }
+}
+
+/// Parses a set of indices like `3,7,10-20`.
+fn parse_selection(s: &str) -> Option<Selection> {
+    let mut ranges = vec![];
+    for part in s.split(',').map(|part| part.trim()) {
+        let mut ends = part.splitn(2, '-');
+        let start = ends.next()?.trim().parse().ok()?;
+        let end = match ends.next() {
+            Some(end) => end.trim().parse().ok()?,
+            None => start,
         };
+        if end < start {
+            return None;
+        }
+        ranges.push((start, end));
+    }
+    Some(Selection { ranges })
+}
 
-        Ok(Command::Process { input_path, output_path })
+/// Parses a grid size like `4x2`.
+fn parse_grid(s: &str) -> Option<(u32, u32)> {
+    let mut parts = s.splitn(2, 'x');
+    let cols = parts.next()?.parse().ok()?;
+    let rows = parts.next()?.parse().ok()?;
+    if cols == 0 || rows == 0 {
+        return None;
     }
+    Some((cols, rows))
 }
@@ -1,6 +1,8 @@
-use abr;
+use abrupng::abr;
+use abrupng::png::SavePngError;
 use getopts;
-use pnglib;
+use glob;
+use rayon;
 use std::io;
 use std::path::PathBuf;
 
@@ -13,10 +15,26 @@ quick_error! {
             cause(reason)
             from()
         }
+        NoInputFiles {
+            description("no input files given")
+            display("no input files given")
+        }
         WrongNumberOfInputFiles(num: usize) {
             description("expected exactly one input file")
             display("expected exactly one input file but got {}", num)
         }
+        UnknownOutputFormat(format: String) {
+            description("unknown output format")
+            display("unknown output format: {}", format)
+        }
+        BadOptionValue(option: &'static str, value: String) {
+            description("bad value for option")
+            display("bad value for --{}: {}", option, value)
+        }
+        BadFilenameTemplate(template: String, reason: String) {
+            description("bad filename template")
+            display("bad filename template {:?}: {}", template, reason)
+        }
         CouldntOpenFile { file_path: PathBuf, err: io::Error } {
             description("couldn't open file")
             display("couldn't open file {}: {}", file_path.display(), err)
@@ -28,6 +46,16 @@ quick_error! {
             cause(err)
             from()
         }
+        CouldntWriteFile { file_path: PathBuf, err: io::Error } {
+            description("couldn't write file")
+            display("couldn't write file {}: {}", file_path.display(), err)
+            cause(err)
+        }
+        CouldntSavePng { file_path: PathBuf, err: SavePngError } {
+            description("couldn't save PNG")
+            display("couldn't save PNG {}: {}", file_path.display(), err)
+            cause(err)
+        }
         CouldntGuessOutputName {
             description("couldn't guess output name from input")
         }
@@ -37,11 +65,54 @@ quick_error! {
                     output_path.display(), err)
             cause(err)
         }
+        OutputFileExists(file_path: PathBuf) {
+            description("output file already exists")
+            display("output file {} already exists", file_path.display())
+        }
+        CouldntReadDir { dir_path: PathBuf, err: io::Error } {
+            description("couldn't read directory")
+            display("couldn't read directory {}: {}", dir_path.display(), err)
+            cause(err)
+        }
+        NoImagesFound(dir_path: PathBuf) {
+            description("no usable images found")
+            display("no usable PNGs found in {}", dir_path.display())
+        }
+        BadInputPattern { pattern: String, err: glob::PatternError } {
+            description("bad glob pattern")
+            display("bad glob pattern {}: {}", pattern, err)
+            cause(err)
+        }
+        NoAbrsFound {
+            description("no ABR files found in the inputs")
+            display("no ABR files found in the inputs")
+        }
+        CouldntStartThreads(err: rayon::ThreadPoolBuildError) {
+            description("couldn't start worker threads")
+            display("couldn't start worker threads: {}", err)
+            cause(err)
+            from()
+        }
+        Output(err: OutputError) {
+            description("couldn't save output file")
+            display("{}", err)
+            cause(err)
+            from()
+        }
+        ConflictingOptions(a: &'static str, b: &'static str) {
+            description("conflicting options")
+            display("--{} and --{} can't be used together", a, b)
+        }
+        TooManyFailures { what: &'static str, failed: usize, total: usize } {
+            description("too many failures")
+            display("{} of {} {} failed, which is more than allowed", failed, total, what)
+        }
     }
 }
 
 quick_error! {
     #[derive(Debug)]
+    #[allow(clippy::enum_variant_names)]
     pub enum ProcessBrushError {
         AbrBrushError(err: abr::BrushError) {
             description("couldn't read brush")
@@ -55,28 +126,63 @@ quick_error! {
             cause(err)
             from()
         }
+        SaveGbrError(err: io::Error) {
+            description("couldn't save GBR")
+            display("couldn't save GBR: {}", err)
+            cause(err)
+            from()
+        }
+        OutputError(err: OutputError) {
+            description("couldn't save file")
+            display("{}", err)
+            cause(err)
+            from()
+        }
     }
 }
 
-
 quick_error! {
     #[derive(Debug)]
-    pub enum SavePngError {
-        EncodingError(err: pnglib::EncodingError) {
-            description("couldn't encode png")
-            display("couldn't encode PNG: {}", err)
+    #[allow(clippy::enum_variant_names)]
+    pub enum ProcessPatternError {
+        AbrPatternError(err: abr::PatternError) {
+            description("couldn't read pattern")
+            display("couldn't read pattern: {}", err)
             cause(err)
             from()
         }
-        IoError(err: io::Error) {
+        SavePngError(err: SavePngError) {
             description("couldn't save PNG")
             display("couldn't save PNG: {}", err)
             cause(err)
             from()
         }
-        BadBitDepth(depth: u16) {
-            description("bad bit-depth")
-            display("bad bit-depth: {}", depth)
+        OutputError(err: OutputError) {
+            description("couldn't save file")
+            display("{}", err)
+            cause(err)
+            from()
+        }
+    }
+}
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum OutputError {
+        Exists(file_path: PathBuf) {
+            description("file already exists")
+            display("{} already exists", file_path.display())
+        }
+        NotOurs(file_path: PathBuf) {
+            description("file exists and wasn't written by abrupng")
+            display("{} already exists and wasn't written by abrupng, so it won't be \
+                     overwritten", file_path.display())
+        }
+        Io(err: io::Error) {
+            description("couldn't put file in place")
+            display("couldn't put file in place: {}", err)
+            cause(err)
+            from()
         }
     }
 }
@@ -0,0 +1,14 @@
+//! Making brush names safe to use as file names.
+
+/// Replaces the characters in `name` that can't go in a file name on some
+/// common filesystem (path separators, characters Windows doesn't allow,
+/// and control characters) with `_`.
+pub fn replace_unsafe_chars(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect()
+}
@@ -0,0 +1,58 @@
+//! Writing brush images as GIMP brushes (`.gbr`).
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// Size of a version 2 header, not counting the name.
+const HEADER_SIZE: u32 = 28;
+
+/// Saves `data`, `width`×`height` 8-bit greyscale samples, as a version 2
+/// GIMP brush at `path`. `spacing` is a percentage of the brush size.
+///
+/// Like ABR, GBR stores a mask where 0 is transparent, so the samples of an
+/// ABR brush can be written as they are.
+pub fn save_greyscale(path: &Path,
+                      data: &[u8],
+                      width: u32,
+                      height: u32,
+                      spacing: u32,
+                      name: &str)
+                      -> io::Result<()> {
+    let mut w = BufWriter::new(File::create(path)?);
+    write_greyscale(&mut w, data, width, height, spacing, name)?;
+    w.flush()
+}
+
+/// Writes a version 2 GIMP brush to `w`. See `save_greyscale`.
+pub fn write_greyscale<W: Write>(w: &mut W,
+                                 data: &[u8],
+                                 width: u32,
+                                 height: u32,
+                                 spacing: u32,
+                                 name: &str)
+                                 -> io::Result<()> {
+    if data.len() != (width as usize) * (height as usize) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                  "data doesn't match brush size"));
+    }
+
+    // The name is NUL-terminated UTF-8, so it can't contain NULs itself.
+    let name = name.replace('\0', "");
+    let name_len = name.len() as u32 + 1;
+
+    write_u32(w, HEADER_SIZE + name_len)?;
+    write_u32(w, 2)?; // version
+    write_u32(w, width)?;
+    write_u32(w, height)?;
+    write_u32(w, 1)?; // bytes per pixel
+    w.write_all(b"GIMP")?;
+    write_u32(w, spacing)?;
+    w.write_all(name.as_bytes())?;
+    w.write_all(&[0])?;
+    w.write_all(data)
+}
+
+fn write_u32<W: Write>(w: &mut W, x: u32) -> io::Result<()> {
+    w.write_all(&x.to_be_bytes())
+}
@@ -0,0 +1,108 @@
+//! Writing sets of brush images as GIMP image pipes (`.gih`).
+
+use gbr;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// How GIMP picks which cell of the pipe to paint with.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Selection {
+    /// Each cell in turn.
+    Incremental,
+    /// By the direction of the stroke.
+    Angular,
+    /// A random cell each time.
+    Random,
+}
+
+impl Selection {
+    /// The name GIMP uses for this mode in a pipe's parameters.
+    pub fn name(self) -> &'static str {
+        match self {
+            Selection::Incremental => "incremental",
+            Selection::Angular => "angular",
+            Selection::Random => "random",
+        }
+    }
+}
+
+/// Parameters of an image pipe.
+#[derive(Debug, Clone)]
+pub struct PipeParams {
+    /// How cells are selected while painting.
+    pub selection: Selection,
+    /// Spacing, as a percentage of the cell size.
+    pub spacing: u32,
+    /// Cells per layer horizontally when GIMP opens the pipe as an image.
+    pub cols: u32,
+    /// Cells per layer vertically when GIMP opens the pipe as an image.
+    pub rows: u32,
+}
+
+impl Default for PipeParams {
+    fn default() -> PipeParams {
+        PipeParams { selection: Selection::Random, spacing: 25, cols: 1, rows: 1 }
+    }
+}
+
+/// One 8-bit greyscale cell of a pipe.
+pub struct Cell<'a> {
+    /// Row-major vector of width×height samples. 0 is transparent.
+    pub data: &'a [u8],
+    /// Image width.
+    pub width: u32,
+    /// Image height.
+    pub height: u32,
+}
+
+/// Saves `cells` as an image pipe called `name` at `path`.
+///
+/// GIMP expects every cell to be the same size, so smaller cells are
+/// centred on a transparent background the size of the largest one.
+pub fn save(path: &Path, name: &str, cells: &[Cell], params: &PipeParams) -> io::Result<()> {
+    let mut w = BufWriter::new(File::create(path)?);
+    write(&mut w, name, cells, params)?;
+    w.flush()
+}
+
+/// Writes an image pipe to `w`. See `save`.
+pub fn write<W: Write>(w: &mut W, name: &str, cells: &[Cell], params: &PipeParams)
+                       -> io::Result<()> {
+    let cell_width = cells.iter().map(|c| c.width).max().unwrap_or(0).max(1);
+    let cell_height = cells.iter().map(|c| c.height).max().unwrap_or(0).max(1);
+
+    // The name is on a line of its own, so it can't contain newlines.
+    let name = name.replace(['\n', '\r'], " ");
+    writeln!(w, "{}", name)?;
+    writeln!(w,
+             "{ncells} ncells:{ncells} cellwidth:{} cellheight:{} step:{} dim:1 \
+              cols:{} rows:{} placement:constant rank0:{ncells} sel0:{}",
+             cell_width,
+             cell_height,
+             params.spacing,
+             params.cols.max(1),
+             params.rows.max(1),
+             params.selection.name(),
+             ncells = cells.len())?;
+
+    let mut buf = vec![];
+    for cell in cells {
+        pad_into(&mut buf, cell, cell_width, cell_height);
+        gbr::write_greyscale(w, &buf, cell_width, cell_height, params.spacing, &name)?;
+    }
+    Ok(())
+}
+
+/// Centres `cell` in a `width`×`height` image, stored in `buf`.
+fn pad_into(buf: &mut Vec<u8>, cell: &Cell, width: u32, height: u32) {
+    buf.clear();
+    buf.resize((width as usize) * (height as usize), 0);
+    let x0 = ((width - cell.width) / 2) as usize;
+    let y0 = ((height - cell.height) / 2) as usize;
+    let (cw, width) = (cell.width as usize, width as usize);
+    for (y, row) in cell.data.chunks(cw.max(1)).take(cell.height as usize).enumerate() {
+        let off = (y0 + y) * width + x0;
+        buf[off..off + row.len()].copy_from_slice(row);
+    }
+}
@@ -0,0 +1,306 @@
+//! The `list` and `info` commands, which print what's in an ABR file
+//! without writing anything.
+
+use abrupng::{abr, manifest};
+use cli::Selection;
+use err::Error;
+use memmap2::Mmap;
+use std::fs::File;
+use std::io::{self, BufReader, Cursor, Read, Seek, SeekFrom};
+use std::iter;
+use std::path::{Path, PathBuf};
+
+/// An open ABR file.
+pub enum Input {
+    /// Read through a buffer.
+    Buffered(BufReader<File>),
+    /// Memory-mapped (with `--mmap`).
+    Mapped(Cursor<Mmap>),
+}
+
+impl Read for Input {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            Input::Buffered(ref mut rdr) => rdr.read(buf),
+            Input::Mapped(ref mut rdr) => rdr.read(buf),
+        }
+    }
+}
+
+impl Seek for Input {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match *self {
+            Input::Buffered(ref mut rdr) => rdr.seek(pos),
+            Input::Mapped(ref mut rdr) => rdr.seek(pos),
+        }
+    }
+}
+
+/// Opens the ABR file at `input_path`. With `mmap`, the file is
+/// memory-mapped (unless it can't be), which saves a system call each time
+/// the decoder moves to another brush.
+pub fn open(input_path: &Path, open_options: &abr::OpenOptions, mmap: bool)
+        -> Result<abr::Brushes<Input>, Error> {
+    let couldnt_open = |e| Error::CouldntOpenFile { file_path: input_path.to_path_buf(), err: e };
+    let file = File::open(input_path).map_err(couldnt_open)?;
+    // Safety: the map is only ever read, and the decoder checks everything
+    // it reads, so another program changing the file underneath us just
+    // gives bad brushes. But if the file is truncated while it's mapped,
+    // reading the pages past the new end raises SIGBUS and abrupng dies.
+    // That's why mapping is opt-in: it's only for when the user knows the
+    // file won't change.
+    let map = if mmap { unsafe { Mmap::map(&file) }.ok() } else { None };
+    let input = match map {
+        Some(map) => Input::Mapped(Cursor::new(map)),
+        None => Input::Buffered(BufReader::new(file)),
+    };
+    abr::open_with_options(input, open_options).map_err(Error::CouldntOpenAbr)
+}
+
+/// Brushes picked out of a file, each with its index and the offset in the
+/// file it was read from.
+pub type Selected = Box<dyn Iterator<Item = (usize, u64, Result<abr::Brush, abr::BrushError>)>>;
+
+/// Iterates over the brushes in `only`, or all of them. With a selection,
+/// the file is indexed first, so the brushes that weren't picked don't have
+/// to be read.
+pub fn select<R: Read + Seek + 'static>(mut brushes: abr::Brushes<R>,
+                                        only: Option<&Selection>)
+                                        -> Selected {
+    let only = match only {
+        Some(only) => only,
+        None => {
+            let mut idx = 0;
+            return Box::new(iter::from_fn(move || {
+                let offset = brushes.next_offset()?;
+                let brush_result = brushes.next()?;
+                idx += 1;
+                Some((idx - 1, offset, brush_result))
+            }));
+        }
+    };
+
+    let mut file = abr::AbrFile::from_brushes(brushes);
+    if only.max() >= file.len() {
+        eprintln!("warning: the file only has {} brushes", file.len());
+    }
+    let picked = (0..file.len()).filter(|&idx| only.contains(idx)).collect::<Vec<_>>();
+    Box::new(picked.into_iter().filter_map(move |idx| {
+        let offset = file.records()[idx].offset;
+        file.brush(idx).map(|brush_result| (idx, offset, brush_result))
+    }))
+}
+
+/// A manifest entry for the brush at `idx`, read from `offset`, before
+/// anything is known about it.
+pub fn new_entry(idx: usize, offset: u64) -> manifest::Entry {
+    manifest::Entry { index: idx, offset: Some(offset), ..Default::default() }
+}
+
+/// Fills in what can be told about `brush` from the file into its manifest
+/// entry. Its name and spacing come from the preset that uses it, if there
+/// is one.
+pub fn describe(entry: &mut manifest::Entry, brush: &abr::Brush, presets: &[abr::BrushPreset]) {
+    match *brush {
+        abr::Brush::Image(ref brush) => {
+            let preset = presets.iter().find(|p| p.uses(brush));
+            entry.width = Some(brush.width);
+            entry.height = Some(brush.height);
+            entry.depth = Some(brush.depth);
+            entry.compression = Some(if brush.compressed { "rle" } else { "raw" });
+            entry.name = preset.map(|p| p.name.clone()).or_else(|| brush.name.clone());
+            entry.uuid = brush.uuid.clone();
+            entry.spacing = preset.and_then(|p| p.spacing)
+                .map(|s| s.round() as u32)
+                .or(brush.spacing.map(|s| s as u32));
+        }
+        abr::Brush::Computed(ref brush) => {
+            entry.width = Some(brush.diameter as u32);
+            entry.height = Some(brush.diameter as u32);
+            entry.compression = Some("computed");
+            entry.name = brush.name.clone();
+            entry.spacing = Some(brush.spacing as u32);
+        }
+    }
+}
+
+/// Like `describe`, but from just the brush's header, so without decoding
+/// it.
+fn describe_header(entry: &mut manifest::Entry,
+                   header: &abr::BrushHeader,
+                   presets: &[abr::BrushPreset]) {
+    let preset = presets.iter().find(|p| p.sampled_data.is_some() && p.sampled_data == header.uuid);
+    entry.width = Some(header.width);
+    entry.height = Some(header.height);
+    entry.depth = header.depth;
+    entry.compression = Some(match (header.computed, header.compressed) {
+        (true, _) => "computed",
+        (false, true) => "rle",
+        (false, false) => "raw",
+    });
+    entry.name = preset.map(|p| p.name.clone()).or_else(|| header.name.clone());
+    entry.uuid = header.uuid.clone();
+    entry.spacing = preset.and_then(|p| p.spacing)
+        .map(|s| s.round() as u32)
+        .or(header.spacing.map(|s| s as u32));
+}
+
+/// Prints a line for each brush in the ABR file at `input_path`: its index,
+/// offset in the file, size, bit-depth, compression and name. With `json`,
+/// prints a manifest instead. With `only`, just the brushes it picks are
+/// listed.
+pub fn list(input_path: &Path,
+            open_options: &abr::OpenOptions,
+            mmap: bool,
+            json: bool,
+            only: Option<&Selection>)
+            -> Result<(), Error> {
+    let mut brushes = open(input_path, open_options, mmap)?;
+    let presets = brushes.presets().unwrap_or_else(|e| {
+        eprintln!("warning: couldn't read brush presets: {}", e);
+        vec![]
+    });
+
+    // Everything listed is in the brush headers, so the samples needn't be
+    // decoded.
+    let file = abr::AbrFile::from_brushes(brushes);
+    if let Some(only) = only {
+        if only.max() >= file.len() {
+            eprintln!("warning: the file only has {} brushes", file.len());
+        }
+    }
+    let entries = file.scan()
+        .iter()
+        .enumerate()
+        .filter(|&(idx, _)| only.is_none_or(|only| only.contains(idx)))
+        .map(|(idx, header_result)| {
+            let mut entry = new_entry(idx, file.records()[idx].offset);
+            match *header_result {
+                Ok(ref header) => describe_header(&mut entry, header, &presets),
+                Err(ref e) => entry.error = Some(e.to_string()),
+            }
+            entry
+        })
+        .collect::<Vec<_>>();
+
+    if json {
+        let stdout = io::stdout();
+        return manifest::write(&mut stdout.lock(), &input_path.to_string_lossy(), &entries)
+            .map_err(|e| Error::CouldntWriteFile { file_path: PathBuf::from("-"), err: e });
+    }
+
+    println!("{:>5}  {:>10}  {:>11}  {:>5}  {:<11}  name",
+             "index", "offset", "size", "depth", "compression");
+    for entry in &entries {
+        let offset = entry.offset.unwrap_or(0);
+        if let Some(ref e) = entry.error {
+            println!("{:>5}  {:>10}  error: {}", entry.index, offset, e);
+            continue;
+        }
+        let size = format!("{}x{}", entry.width.unwrap_or(0), entry.height.unwrap_or(0));
+        let depth = entry.depth.map(|d| d.to_string()).unwrap_or_else(|| "-".to_string());
+        let line = format!("{:>5}  {:>10}  {:>11}  {:>5}  {:<11}  {}",
+                           entry.index,
+                           offset,
+                           size,
+                           depth,
+                           entry.compression.unwrap_or("-"),
+                           entry.name.as_ref().map(|s| &s[..]).unwrap_or(""));
+        println!("{}", line.trim_end());
+    }
+
+    Ok(())
+}
+
+/// Prints the version of the ABR file at `input_path`, the blocks it's made
+/// of, and how many brushes, presets and patterns it has. The brushes and
+/// patterns are summed up from their headers, without decoding them.
+pub fn info(input_path: &Path, open_options: &abr::OpenOptions, mmap: bool)
+            -> Result<(), Error> {
+    let mut brushes = open(input_path, open_options, mmap)?;
+
+    println!("file:       {}", input_path.display());
+    match brushes.subversion() {
+        Some(subversion) => println!("version:    {}.{}", brushes.version(), subversion),
+        None => println!("version:    {}", brushes.version()),
+    }
+
+    let blocks = brushes.blocks().to_vec();
+    if blocks.is_empty() {
+        println!("blocks:     none");
+    } else {
+        println!("blocks:");
+        for block in &blocks {
+            println!("    {} {}  offset {:>10}  length {:>10}",
+                     block.signature, block.key, block.offset, block.len);
+        }
+    }
+
+    let presets = brushes.presets();
+    let patterns = brushes.count_patterns();
+
+    let file = abr::AbrFile::from_brushes(brushes);
+    let headers = file.scan();
+    let failed = headers.iter().filter(|h| h.is_err()).count();
+    if failed == 0 {
+        println!("brushes:    {}", headers.len());
+    } else {
+        println!("brushes:    {} ({} couldn't be read)", headers.len(), failed);
+    }
+    let headers = headers.iter().filter_map(|h| h.as_ref().ok()).collect::<Vec<_>>();
+    if !headers.is_empty() {
+        print_headers(&headers);
+    }
+
+    match presets {
+        Ok(presets) => println!("presets:    {}", presets.len()),
+        Err(e) => println!("presets:    error: {}", e),
+    }
+
+    println!("patterns:   {}", patterns);
+
+    Ok(())
+}
+
+/// Prints a summary of the brushes described by `headers`: the range of
+/// sizes, how many have each bit-depth and compression, and how much room
+/// the compressed ones take.
+fn print_headers(headers: &[&abr::BrushHeader]) {
+    let widths = headers.iter().map(|h| h.width);
+    let heights = headers.iter().map(|h| h.height);
+    println!("    widths:       {} to {}",
+             widths.clone().min().unwrap_or(0), widths.max().unwrap_or(0));
+    println!("    heights:      {} to {}",
+             heights.clone().min().unwrap_or(0), heights.max().unwrap_or(0));
+
+    let count = |f: &dyn Fn(&abr::BrushHeader) -> bool| headers.iter().filter(|h| f(h)).count();
+    println!("    depths:       {}", counts(&[
+        ("8-bit", count(&|h| h.depth == Some(8))),
+        ("16-bit", count(&|h| h.depth == Some(16))),
+        ("computed", count(&|h| h.computed)),
+    ]));
+    println!("    compression:  {}", counts(&[
+        ("raw", count(&|h| !h.computed && !h.compressed)),
+        ("rle", count(&|h| h.compressed)),
+        ("computed", count(&|h| h.computed)),
+    ]));
+
+    let compressed = headers.iter().filter(|h| h.compressed);
+    let rle_len = compressed.clone().filter_map(|h| h.compressed_len).sum::<u64>();
+    let raw_len = compressed
+        .map(|h| h.width as u64 * h.height as u64 * h.depth.unwrap_or(8) as u64 / 8)
+        .sum::<u64>();
+    if raw_len != 0 {
+        println!("    rle data:     {} bytes ({} uncompressed)", rle_len, raw_len);
+    }
+}
+
+/// Formats how many of each kind of thing there are, like `raw 3, rle 2`,
+/// leaving out the kinds there are none of.
+fn counts(counts: &[(&str, usize)]) -> String {
+    counts.iter()
+        .filter(|&&(_, n)| n != 0)
+        .map(|&(name, n)| format!("{} {}", name, n))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
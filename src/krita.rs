@@ -0,0 +1,223 @@
+//! Writing sets of brush images as Krita resource bundles (`.bundle`).
+//!
+//! A bundle is a zip file holding the resources, a manifest listing them and
+//! some metadata. Each brush image goes in as a GIMP brush tip, along with a
+//! paintop preset (`.kpp`) that paints with it, so the brushes show up ready
+//! to use once the bundle is imported.
+
+use gbr;
+use md5;
+use png;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use util;
+use zip::ZipWriter;
+
+/// Information about the bundle as a whole.
+#[derive(Debug, Clone, Default)]
+pub struct Metadata {
+    /// Title of the bundle, shown in Krita's bundle manager.
+    pub title: String,
+    /// Longer description of the bundle.
+    pub description: String,
+    /// Who made the brushes.
+    pub author: String,
+}
+
+/// One 8-bit greyscale brush tip to put in a bundle.
+pub struct Tip<'a> {
+    /// Row-major vector of width×height samples. 0 is transparent.
+    pub data: &'a [u8],
+    /// Image width.
+    pub width: u32,
+    /// Image height.
+    pub height: u32,
+    /// Name of the brush.
+    pub name: &'a str,
+    /// Spacing, as a percentage of the brush size.
+    pub spacing: u32,
+}
+
+const MIMETYPE: &str = "application/x-krita-resourcebundle";
+
+/// Krita's preset thumbnails are this size.
+const PRESET_THUMBNAIL_SIZE: u32 = 200;
+
+/// Size of the bundle's preview, and how many tips go across it.
+const PREVIEW_SIZE: u32 = 256;
+const PREVIEW_COLS: u32 = 4;
+
+/// The bundle's creation date. Like the zip entries' times, it's fixed, so
+/// converting the same brushes always gives the same bundle.
+const DATE: &str = "1980-01-01T00:00:00";
+
+/// Saves `tips` as a Krita resource bundle at `path`.
+pub fn save(path: &Path, meta: &Metadata, tips: &[Tip]) -> io::Result<()> {
+    let w = BufWriter::new(File::create(path)?);
+    let mut w = write(w, meta, tips)?;
+    w.flush()
+}
+
+/// Writes a Krita resource bundle to `w`, returning it when done. See `save`.
+pub fn write<W: Write>(w: W, meta: &Metadata, tips: &[Tip]) -> io::Result<W> {
+    let mut zip = ZipWriter::new(w);
+    // Like ODF, the mimetype comes first and uncompressed so it can be sniffed.
+    zip.add_stored("mimetype", MIMETYPE.as_bytes())?;
+
+    // (media type, path, md5) of every resource, for the manifest.
+    let mut resources = vec![];
+    let mut buf = vec![];
+    for (idx, tip) in tips.iter().enumerate() {
+        let file_stem = format!("{:03} {}", idx, util::sanitize_file_name(tip.name));
+
+        let brush_path = format!("brushes/{}.gbr", file_stem);
+        buf.clear();
+        gbr::write_greyscale(&mut buf, tip.data, tip.width, tip.height, tip.spacing, tip.name)?;
+        zip.add_deflated(&brush_path, &buf)?;
+        resources.push(("brushes", brush_path, md5::compute(&buf)));
+
+        let preset_path = format!("paintoppresets/{}.kpp", file_stem);
+        buf.clear();
+        write_preset(&mut buf, tip, &format!("{}.gbr", file_stem))?;
+        // The preset is a PNG, which is already compressed.
+        zip.add_stored(&preset_path, &buf)?;
+        resources.push(("paintoppresets", preset_path, md5::compute(&buf)));
+    }
+
+    zip.add_deflated("META-INF/manifest.xml", manifest_xml(&resources).as_bytes())?;
+    zip.add_deflated("meta.xml", meta_xml(meta, tips.len()).as_bytes())?;
+
+    buf.clear();
+    write_preview(&mut buf, tips)?;
+    zip.add_stored("preview.png", &buf)?;
+
+    zip.finish()
+}
+
+fn manifest_xml(resources: &[(&str, String, md5::Digest)]) -> String {
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str("<manifest:manifest \
+                  xmlns:manifest=\"urn:oasis:names:tc:opendocument:xmlns:manifest:1.0\" \
+                  manifest:version=\"1.2\">\n");
+    xml.push_str(&format!(" <manifest:file-entry manifest:media-type=\"{}\" \
+                           manifest:full-path=\"/\"/>\n",
+                          MIMETYPE));
+    for &(media_type, ref path, ref md5) in resources {
+        xml.push_str(&format!(" <manifest:file-entry manifest:media-type=\"{}\" \
+                               manifest:full-path=\"{}\" manifest:md5sum=\"{:x}\"/>\n",
+                              media_type,
+                              escape_xml(path),
+                              md5));
+    }
+    xml.push_str("</manifest:manifest>\n");
+    xml
+}
+
+fn meta_xml(meta: &Metadata, num_tips: usize) -> String {
+    let description = if meta.description.is_empty() {
+        format!("{} brushes converted from Photoshop.", num_tips)
+    } else {
+        meta.description.clone()
+    };
+
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str("<meta:meta xmlns:meta=\"urn:oasis:names:tc:opendocument:xmlns:meta:1.0\" \
+                  xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n");
+    xml.push_str(&format!(" <meta:generator>abrupng {}</meta:generator>\n",
+                          env!("CARGO_PKG_VERSION")));
+    xml.push_str(&format!(" <dc:author>{}</dc:author>\n", escape_xml(&meta.author)));
+    xml.push_str(&format!(" <dc:title>{}</dc:title>\n", escape_xml(&meta.title)));
+    xml.push_str(&format!(" <dc:description>{}</dc:description>\n", escape_xml(&description)));
+    xml.push_str(&format!(" <meta:initial-creator>{}</meta:initial-creator>\n",
+                          escape_xml(&meta.author)));
+    xml.push_str(&format!(" <dc:creator>{}</dc:creator>\n", escape_xml(&meta.author)));
+    xml.push_str(&format!(" <meta:creation-date>{}</meta:creation-date>\n", DATE));
+    xml.push_str(&format!(" <meta:dc-date>{}</meta:dc-date>\n", DATE));
+    xml.push_str("</meta:meta>\n");
+    xml
+}
+
+/// Writes a paintop preset for the pixel brush engine that paints with
+/// `tip`, stored in the bundle as `brush_file`.
+///
+/// A `.kpp` is a PNG thumbnail of the preset with its settings, as XML, in a
+/// text chunk.
+fn write_preset<W: Write>(w: W, tip: &Tip, brush_file: &str) -> io::Result<()> {
+    let brush_definition = format!("<Brush type=\"gbr_brush\" BrushVersion=\"2\" \
+                                    filename=\"{}\" spacing=\"{}\" useAutoSpacing=\"0\" \
+                                    autoSpacingCoeff=\"1\" angle=\"0\" scale=\"1\" \
+                                    ColorAsMask=\"1\"/>",
+                                   escape_xml(brush_file),
+                                   tip.spacing as f64 / 100.0);
+    let params = [
+        ("brush_definition", &brush_definition[..]),
+        ("requiredBrushFile", brush_file),
+        ("paintop", "paintbrush"),
+    ];
+
+    let mut xml = format!("<Preset paintopid=\"paintbrush\" name=\"{}\">", escape_xml(tip.name));
+    for &(name, value) in &params {
+        xml.push_str(&format!("<param type=\"string\" name=\"{}\"><![CDATA[{}]]></param>",
+                              name,
+                              escape_cdata(value)));
+    }
+    xml.push_str("</Preset>");
+
+    let data = util::thumbnail(tip.data,
+                               tip.width,
+                               tip.height,
+                               PRESET_THUMBNAIL_SIZE,
+                               PRESET_THUMBNAIL_SIZE);
+    png::write_with_text(w,
+                         &data,
+                         PRESET_THUMBNAIL_SIZE,
+                         PRESET_THUMBNAIL_SIZE,
+                         8,
+                         png::ColorType::Greyscale,
+                         &[("version", "2.2"), ("preset", &xml)])
+        .map_err(io::Error::other)
+}
+
+/// Writes the bundle's preview: a grid of the first few tips.
+fn write_preview<W: Write>(w: W, tips: &[Tip]) -> io::Result<()> {
+    let cell = PREVIEW_SIZE / PREVIEW_COLS;
+    let mut data = vec![255; (PREVIEW_SIZE * PREVIEW_SIZE) as usize];
+    for (idx, tip) in tips.iter().take((PREVIEW_COLS * PREVIEW_COLS) as usize).enumerate() {
+        let (x0, y0) = ((idx as u32 % PREVIEW_COLS) * cell, (idx as u32 / PREVIEW_COLS) * cell);
+        let thumb = util::thumbnail(tip.data, tip.width, tip.height, cell, cell);
+        for (y, row) in thumb.chunks(cell as usize).enumerate() {
+            let off = ((y0 + y as u32) * PREVIEW_SIZE + x0) as usize;
+            data[off..off + row.len()].copy_from_slice(row);
+        }
+    }
+    png::write(w, &data, PREVIEW_SIZE, PREVIEW_SIZE, 8, png::ColorType::Greyscale)
+        .map_err(io::Error::other)
+}
+
+/// Escapes `s` for use in XML text or attribute values. Non-ASCII characters
+/// become character references, since presets are stored in Latin-1 PNG
+/// text chunks.
+fn escape_xml(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            c if c.is_ascii() && !c.is_control() => out.push(c),
+            c => out.push_str(&format!("&#{};", c as u32)),
+        }
+    }
+    out
+}
+
+/// Makes `s` safe to put in a CDATA section, by splitting any `]]>` across
+/// two sections.
+fn escape_cdata(s: &str) -> String {
+    s.replace("]]>", "]]]]><![CDATA[>")
+}
@@ -0,0 +1,51 @@
+//! Library for reading image brushes out of Adobe Photoshop's ABR files.
+//!
+//! The ABR decoder lives in the [`abr`](abr/index.html) module. Decoded
+//! brushes can be written out with the PNG writer in [`png`](png/index.html)
+//! or the GIMP brush writer in [`gbr`](gbr/index.html), or packed together
+//! into a GIMP image pipe with [`gih`](gih/index.html), a Krita resource
+//! bundle with [`krita`](krita/index.html), or a MyPaint brush pack with
+//! [`mypaint`](mypaint/index.html), or packed into texture atlases with
+//! [`atlas`](atlas/index.html). [`manifest`](manifest/index.html)
+//! writes a JSON description of the brushes in a file, and
+//! [`preview`](preview/index.html) draws them all on one contact sheet.
+//!
+//! ```no_run
+//! use std::fs::File;
+//! use std::io::BufReader;
+//! use std::path::Path;
+//!
+//! let rdr = BufReader::new(File::open("mybrushes.abr").unwrap());
+//! for (idx, brush) in abrupng::abr::open(rdr).unwrap().enumerate() {
+//!     let brush = brush.unwrap().into_image();
+//!     let path = format!("{}.png", idx);
+//!     abrupng::png::save_greyscale(Path::new(&path),
+//!                                  &brush.data[..],
+//!                                  brush.width,
+//!                                  brush.height,
+//!                                  brush.depth).unwrap();
+//! }
+//! ```
+
+extern crate deflate;
+extern crate md5;
+extern crate png as pnglib;
+#[macro_use]
+extern crate quick_error;
+
+pub mod abr;
+pub mod atlas;
+// Internal: shared with the abrupng binary's file name templates, and not
+// part of the library's API.
+#[doc(hidden)]
+pub mod filename;
+pub mod gbr;
+pub mod gih;
+pub mod krita;
+pub mod manifest;
+pub mod mypaint;
+pub mod png;
+pub mod preview;
+mod json;
+mod util;
+mod zip;
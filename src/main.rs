@@ -1,20 +1,29 @@
 //! Command-line utility for converting an Adobe ABR file to the
-//! brushes it contains (as PNGs).
+//! brushes it contains (as PNGs, GIMP brushes, a GIMP image pipe, a Krita
+//! resource bundle, or a MyPaint brush pack).
 
-extern crate byteorder;
+extern crate abrupng;
 extern crate getopts;
-extern crate png as pnglib;
+extern crate glob;
+extern crate memmap2;
 #[macro_use]
 extern crate quick_error;
+extern crate rayon;
 
-mod abr;
+mod batch;
 mod cli;
 mod err;
-mod png;
+mod inspect;
+mod output;
+mod png2abr;
+mod template;
 
-use err::{Error, ProcessBrushError};
+use abrupng::{abr, atlas, gbr, gih, krita, manifest, mypaint, png, preview};
+use err::{Error, ProcessBrushError, ProcessPatternError};
+use output::{OutputDir, Saved};
+use template::FileNamer;
 use std::fs::File;
-use std::io;
+use std::io::{self, BufWriter, Write};
 use std::path::{Path, PathBuf};
 
 fn main() {
@@ -24,15 +33,26 @@ fn main() {
 
 /// C-style main function.
 fn main2() -> i32 {
-    let opts = cli::make_options();
-    let result = cli::parse_cli_options(&opts).and_then(|command| {
+    let result = cli::parse_cli_options().and_then(|command| {
         match command {
-            cli::Command::Help => {
-                cli::print_usage(&opts);
+            cli::Command::Help(usage) => {
+                print!("{}", usage);
                 Ok(())
             }
-            cli::Command::Process { input_path, output_path } => {
-                process(input_path, output_path)
+            cli::Command::Extract { inputs, output_path, options, batch } => {
+                extract(&inputs, output_path, &options, &batch)
+            }
+            cli::Command::List { input_path, open_options, mmap, json, only } => {
+                inspect::list(&input_path, &open_options, mmap, json, only.as_ref())
+            }
+            cli::Command::Info { input_path, open_options, mmap } => {
+                inspect::info(&input_path, &open_options, mmap)
+            }
+            cli::Command::Preview { input_path, output_path, open_options, mmap, params } => {
+                process_preview(&input_path, &output_path, &open_options, mmap, &params)
+            }
+            cli::Command::Png2Abr { input_dir, output_path, options } => {
+                png2abr::png2abr(&input_dir, &output_path, &options)
             }
         }
     });
@@ -43,50 +63,576 @@ fn main2() -> i32 {
     }
 }
 
-/// Reads an ABR file at `input_path` and extracts the image brushes
-/// as PNGs, writing them to the directory `output_path`.
-fn process(input_path: PathBuf, output_path: PathBuf) -> Result<(), Error> {
-    let file = File::open(&input_path)
-        .map_err(|e| Error::CouldntOpenFile {
-            file_path: input_path,
-            err: e,
-        })?;
-    let rdr = std::io::BufReader::new(file);
+/// Extracts the brushes from `inputs`. A single file is extracted to
+/// `output_path`, or else a directory named after it; several each get
+/// their own directory in `output_path`, or else the current directory.
+fn extract(inputs: &[String],
+           output_path: Option<PathBuf>,
+           options: &cli::ExtractOptions,
+           batch_options: &cli::BatchOptions)
+           -> Result<(), Error> {
+    if batch::is_single_file(inputs) {
+        let input_path = Path::new(&inputs[0]);
+        // If no output directory is given, try to guess one from the stem of
+        // the input file (ex. mybrushes.abr => ./mybrushes).
+        let output_path = match output_path {
+            Some(output_path) => output_path,
+            None => match input_path.file_stem() {
+                Some(stem) => PathBuf::from(stem),
+                None => return Err(Error::CouldntGuessOutputName),
+            },
+        };
+        let summary = process(input_path, &output_path, options)?;
+        return batch::check_brush_failures(batch_options, &summary);
+    }
 
-    let brushes = abr::open(rdr)
-        .map_err(|e| Error::CouldntOpenAbr(e))?;
+    let inputs = batch::expand_inputs(inputs)?;
+    let output_path = output_path.unwrap_or_else(|| PathBuf::from("."));
+    batch::extract_all(&inputs, &output_path, batch_options, |input_path, output_path| {
+        process(input_path, output_path, options)
+    })
+}
 
-    std::fs::create_dir(&output_path)
-        .map_err(|e| Error::CouldntCreateOutputDir {
-            output_path: output_path.clone(),
-            err: e,
+/// Reads an ABR file at `input_path` and extracts the image brushes,
+/// writing them to the directory `output_path`.
+fn process(input_path: &Path,
+           output_path: &Path,
+           options: &cli::ExtractOptions)
+           -> Result<batch::Summary, Error> {
+    let open_options = abr::OpenOptions { guess_unknown_versions: options.guess_format };
+    let mut brushes = inspect::open(input_path, &open_options, options.mmap)?;
+    let presets = brushes.presets().unwrap_or_else(|e| {
+        eprintln!("warning: couldn't read brush presets: {}", e);
+        vec![]
+    });
+    let patterns = brushes.patterns();
+
+    // Used to name brushes that don't have a name of their own.
+    let stem = input_path.file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let mut out = OutputDir::create(output_path, options.existing)?;
+    // Record what was written even if something fails partway.
+    let brushes = inspect::select(brushes, options.only.as_ref());
+    let result = write_output(brushes, &presets, patterns, &mut out, input_path, &stem, options);
+    out.finish()?;
+    let entries = result?;
+
+    Ok(batch::Summary {
+        brushes: entries.len(),
+        failed: entries.iter().filter(|entry| entry.error.is_some()).count(),
+    })
+}
+
+/// Writes the brushes and patterns read out of the ABR at `input_path` into
+/// `out`, returning the manifest entries for the brushes.
+fn write_output(brushes: inspect::Selected,
+                presets: &[abr::BrushPreset],
+                patterns: Vec<Result<abr::Pattern, abr::PatternError>>,
+                out: &mut OutputDir,
+                input_path: &Path,
+                stem: &str,
+                options: &cli::ExtractOptions)
+                -> Result<Vec<manifest::Entry>, Error> {
+    let mut entries = vec![];
+    match options.format {
+        cli::OutputFormat::Gih => {
+            process_pipe(brushes, presets, out, stem, options, &mut entries)?
+        }
+        cli::OutputFormat::Bundle => {
+            process_bundle(brushes, presets, out, stem, &mut entries)?
+        }
+        cli::OutputFormat::MyPaint => {
+            process_mypaint(brushes, presets, out, stem, &mut entries)?
+        }
+        cli::OutputFormat::Atlas => {
+            process_atlas(brushes, presets, out, stem, options, &mut entries)?
+        }
+        _ => {
+            let mut namer = FileNamer::new(&options.filename, stem, options.format.extension());
+            for (idx, offset, brush_result) in brushes {
+                let default_name = format!("{} {}", stem, idx);
+                let mut entry = inspect::new_entry(idx, offset);
+                match process_brush(brush_result, out, &mut namer, presets, &default_name,
+                                    options, &mut entry) {
+                    Ok(saved) => saved.report(),
+                    Err(e) => {
+                        eprintln!("error on brush {}: {}", idx, e);
+                        entry.error = Some(e.to_string());
+                    }
+                }
+                entries.push(entry);
+            }
+        }
+    }
+
+    if options.manifest {
+        let save_path = out.path().join("manifest.json");
+        let saved = out.save("manifest.json", |path| {
+            manifest::save(path, &input_path.to_string_lossy(), &entries)
+                .map_err(|e| Error::CouldntWriteFile {
+                    file_path: save_path.clone(),
+                    err: e,
+                })
         })?;
+        saved.report();
+    }
+
+    if !patterns.is_empty() {
+        process_patterns(patterns, out);
+    }
+
+    Ok(entries)
+}
+
+/// Draws all the brushes in the ABR file at `input_path` on one contact
+/// sheet, saved at `output_path`. Brushes that fail to read get a crossed-out
+/// cell.
+fn process_preview(input_path: &Path,
+                   output_path: &Path,
+                   open_options: &abr::OpenOptions,
+                   mmap: bool,
+                   params: &preview::SheetParams)
+                   -> Result<(), Error> {
+    if output_path.exists() {
+        return Err(Error::OutputFileExists(output_path.to_path_buf()));
+    }
 
+    let brushes = inspect::open(input_path, open_options, mmap)?;
+    let mut cells = vec![];
     for (idx, brush_result) in brushes.enumerate() {
-        let save_path = output_path.join(Path::new(&format!("{}.png", idx)));
-        match process_brush(brush_result, &save_path) {
-            Ok(()) => println!("Wrote {}.", save_path.display()),
-            Err(e) => eprintln!("error on brush {}: {}", idx, e),
+        match brush_result {
+            Ok(brush) => {
+                let brush = brush.into_image().into_8bit();
+                cells.push(preview::Cell::tip(&brush.data, brush.width, brush.height, params));
+            }
+            Err(e) => {
+                eprintln!("error on brush {}: {}", idx, e);
+                cells.push(preview::Cell::Failed);
+            }
         }
     }
 
+    preview::save(output_path, &cells, params)
+        .map_err(|e| Error::CouldntSavePng {
+            file_path: output_path.to_path_buf(),
+            err: e,
+        })?;
+    println!("Wrote {} ({} brushes).", output_path.display(), cells.len());
+
     Ok(())
 }
 
-/// Saves the result of reading out a brush to `save_path`. Returns an
-/// error if either the reading failed or the writing fails.
-fn process_brush(brush_result: Result<abr::ImageBrush, abr::BrushError>,
-              save_path: &Path)
-              -> Result<(), ProcessBrushError> {
+/// Saves the patterns read out of an ABR as PNGs in a `patterns`
+/// subdirectory of `out`.
+fn process_patterns(patterns: Vec<Result<abr::Pattern, abr::PatternError>>, out: &mut OutputDir) {
+    for (idx, pattern_result) in patterns.into_iter().enumerate() {
+        let file_name = format!("patterns/{}.png", idx);
+        match process_pattern(pattern_result, out, &file_name) {
+            Ok(saved) => saved.report(),
+            Err(e) => eprintln!("error on pattern {}: {}", idx, e),
+        }
+    }
+}
+
+/// Saves the result of reading out a pattern to `file_name` in `out`.
+fn process_pattern(pattern_result: Result<abr::Pattern, abr::PatternError>,
+                   out: &mut OutputDir,
+                   file_name: &str)
+                   -> Result<Saved, ProcessPatternError> {
+    let pattern = pattern_result?;
+    let color = match pattern.color {
+        abr::PatternColor::Greyscale => png::ColorType::Greyscale,
+        abr::PatternColor::GreyscaleAlpha => png::ColorType::GreyscaleAlpha,
+        abr::PatternColor::Rgb => png::ColorType::Rgb,
+        abr::PatternColor::Rgba => png::ColorType::Rgba,
+    };
+    out.save(file_name, |save_path| {
+        png::save(save_path,
+                  &pattern.data[..],
+                  pattern.width,
+                  pattern.height,
+                  pattern.depth,
+                  color)?;
+        Ok(())
+    })
+}
+
+/// Saves the result of reading out a brush in `out`, in a file named by
+/// `namer`. Returns an error if either the reading failed or the writing
+/// fails.
+///
+/// The brush's name and spacing come from the preset that uses it, or the
+/// brush itself, or else `default_name` and GIMP's default spacing. What's
+/// read about the brush goes in its manifest `entry`.
+fn process_brush(brush_result: Result<abr::Brush, abr::BrushError>,
+              out: &mut OutputDir,
+              namer: &mut FileNamer,
+              presets: &[abr::BrushPreset],
+              default_name: &str,
+              options: &cli::ExtractOptions,
+              entry: &mut manifest::Entry)
+              -> Result<Saved, ProcessBrushError> {
     let brush = brush_result?;
-    png::save_greyscale(save_path,
-                        &brush.data[..],
-                        brush.width,
-                        brush.height,
-                        brush.depth)?;
+    inspect::describe(entry, &brush, presets);
+    // Computed brushes get rasterised so they produce an image too.
+    let mut brush = brush.into_image();
+    if options.eight_bit {
+        brush = brush.into_8bit();
+    }
+    let preset = presets.iter().find(|p| p.uses(&brush));
+
+    let file_name = namer.file_name(entry);
+    let saved = out.save(&file_name, |save_path| -> Result<(), ProcessBrushError> {
+        match options.format {
+            cli::OutputFormat::Png => {
+                png::save_mask(save_path,
+                               &brush.data[..],
+                               brush.width,
+                               brush.height,
+                               brush.depth,
+                               options.png_style,
+                               options.invert)?;
+            }
+            cli::OutputFormat::Gbr => {
+                let name = brush_name(&brush, preset, default_name);
+                let spacing = brush_spacing(&brush, preset).unwrap_or(25);
+                // GBR is 8-bit only.
+                let brush = brush.into_8bit();
+                gbr::save_greyscale(save_path,
+                                    &brush.data[..],
+                                    brush.width,
+                                    brush.height,
+                                    spacing,
+                                    &name)?;
+            }
+            cli::OutputFormat::Gih => unreachable!("pipes are written by process_pipe"),
+            cli::OutputFormat::Bundle => unreachable!("bundles are written by process_bundle"),
+            cli::OutputFormat::MyPaint => {
+                unreachable!("brush packs are written by process_mypaint")
+            }
+            cli::OutputFormat::Atlas => unreachable!("atlases are written by process_atlas"),
+        }
+        Ok(())
+    })?;
+    entry.file_name = Some(file_name);
+    Ok(saved)
+}
+
+/// Packs all the brushes into one GIMP image pipe, named after `stem`, in
+/// `out`. Brushes that fail to read are left out.
+fn process_pipe(brushes: inspect::Selected,
+                presets: &[abr::BrushPreset],
+                out: &mut OutputDir,
+                stem: &str,
+                options: &cli::ExtractOptions,
+                entries: &mut Vec<manifest::Entry>)
+                -> Result<(), Error> {
+    let mut tips = vec![];
+    for (idx, offset, brush_result) in brushes {
+        let mut entry = inspect::new_entry(idx, offset);
+        match brush_result {
+            Ok(brush) => {
+                inspect::describe(&mut entry, &brush, presets);
+                // Pipes are made of GBRs, which are 8-bit only.
+                tips.push(brush.into_image().into_8bit());
+            }
+            Err(e) => {
+                eprintln!("error on brush {}: {}", idx, e);
+                entry.error = Some(e.to_string());
+            }
+        }
+        entries.push(entry);
+    }
+
+    // The pipe has just one spacing; use the first brush that has one.
+    let mut params = options.pipe.clone();
+    params.spacing = tips.iter()
+        .filter_map(|brush| {
+            let preset = presets.iter().find(|p| p.uses(brush));
+            brush_spacing(brush, preset)
+        })
+        .next()
+        .unwrap_or(params.spacing);
+
+    let cells = tips.iter()
+        .map(|brush| gih::Cell { data: &brush.data[..], width: brush.width, height: brush.height })
+        .collect::<Vec<_>>();
+    let name = if stem.is_empty() { "brushes" } else { stem };
+    let file_name = format!("{}.gih", name);
+    let save_path = out.path().join(&file_name);
+    let saved = out.save(&file_name, |path| {
+        gih::save(path, name, &cells, &params)
+            .map_err(|e| Error::CouldntWriteFile {
+                file_path: save_path.clone(),
+                err: e,
+            })
+    })?;
+    report_pack(&saved, cells.len());
+    set_file_name(entries, &save_path);
+
+    Ok(())
+}
+
+/// Packs all the brushes into one Krita resource bundle, named after
+/// `stem`, in `out`. Brushes that fail to read are
+/// left out.
+fn process_bundle(brushes: inspect::Selected,
+                  presets: &[abr::BrushPreset],
+                  out: &mut OutputDir,
+                  stem: &str,
+                  entries: &mut Vec<manifest::Entry>)
+                  -> Result<(), Error> {
+    let title = if stem.is_empty() { "brushes" } else { stem };
+
+    let mut tips = vec![];
+    for (idx, offset, brush_result) in brushes {
+        let mut entry = inspect::new_entry(idx, offset);
+        match brush_result {
+            Ok(brush) => {
+                inspect::describe(&mut entry, &brush, presets);
+                let brush = brush.into_image();
+                let preset = presets.iter().find(|p| p.uses(&brush));
+                let name = brush_name(&brush, preset, &format!("{} {}", title, idx));
+                let spacing = brush_spacing(&brush, preset).unwrap_or(25);
+                // Tips are stored as GBRs, which are 8-bit only.
+                tips.push((brush.into_8bit(), name, spacing));
+            }
+            Err(e) => {
+                eprintln!("error on brush {}: {}", idx, e);
+                entry.error = Some(e.to_string());
+            }
+        }
+        entries.push(entry);
+    }
+
+    let tips = tips.iter()
+        .map(|&(ref brush, ref name, spacing)| krita::Tip {
+            data: &brush.data[..],
+            width: brush.width,
+            height: brush.height,
+            name,
+            spacing,
+        })
+        .collect::<Vec<_>>();
+    let meta = krita::Metadata { title: title.to_string(), ..Default::default() };
+    let file_name = format!("{}.bundle", title);
+    let save_path = out.path().join(&file_name);
+    let saved = out.save(&file_name, |path| {
+        krita::save(path, &meta, &tips)
+            .map_err(|e| Error::CouldntWriteFile {
+                file_path: save_path.clone(),
+                err: e,
+            })
+    })?;
+    report_pack(&saved, tips.len());
+    set_file_name(entries, &save_path);
+
+    Ok(())
+}
+
+/// Packs all the brushes into one MyPaint brush pack, named after `stem`,
+/// in `out`. Brushes that fail to read are left out.
+fn process_mypaint(brushes: inspect::Selected,
+                   presets: &[abr::BrushPreset],
+                   out: &mut OutputDir,
+                   stem: &str,
+                   entries: &mut Vec<manifest::Entry>)
+                   -> Result<(), Error> {
+    let group = if stem.is_empty() { "brushes" } else { stem };
+
+    let mut brushes_read = vec![];
+    for (idx, offset, brush_result) in brushes {
+        let mut entry = inspect::new_entry(idx, offset);
+        match brush_result {
+            Ok(brush) => {
+                inspect::describe(&mut entry, &brush, presets);
+                // Computed brushes have the shape parameters MyPaint wants;
+                // keep them around for after rasterising.
+                let computed = match brush {
+                    abr::Brush::Computed(ref computed) => Some(computed.clone()),
+                    abr::Brush::Image(_) => None,
+                };
+                // Previews are 8-bit.
+                let brush = brush.into_image().into_8bit();
+                let preset = presets.iter().find(|p| p.uses(&brush)).cloned();
+                let name = brush_name(&brush, preset.as_ref(), &format!("{} {}", group, idx));
+                brushes_read.push((brush, computed, preset, name));
+            }
+            Err(e) => {
+                eprintln!("error on brush {}: {}", idx, e);
+                entry.error = Some(e.to_string());
+            }
+        }
+        entries.push(entry);
+    }
+
+    let tips = brushes_read.iter()
+        .map(|(brush, computed, preset, name)| {
+            let preset = preset.as_ref();
+            let computed = computed.as_ref();
+            mypaint::Tip {
+                data: &brush.data[..],
+                width: brush.width,
+                height: brush.height,
+                name,
+                diameter: preset.and_then(|p| p.diameter)
+                    .unwrap_or(brush.width.max(brush.height) as f64),
+                spacing: brush_spacing(brush, preset).map(|s| s as f64),
+                hardness: preset.and_then(|p| p.hardness)
+                    .or(computed.map(|c| c.hardness as f64)),
+                roundness: preset.and_then(|p| p.roundness)
+                    .or(computed.map(|c| c.roundness as f64)),
+                angle: preset.and_then(|p| p.angle)
+                    .or(computed.map(|c| c.angle as f64)),
+                antialias: preset.and_then(|p| p.antialias).or(brush.antialias),
+            }
+        })
+        .collect::<Vec<_>>();
+    let file_name = format!("{}.zip", group);
+    let save_path = out.path().join(&file_name);
+    let saved = out.save(&file_name, |path| {
+        mypaint::save(path, group, &tips)
+            .map_err(|e| Error::CouldntWriteFile {
+                file_path: save_path.clone(),
+                err: e,
+            })
+    })?;
+    report_pack(&saved, tips.len());
+    set_file_name(entries, &save_path);
+
+    Ok(())
+}
+
+/// Packs all the brushes into texture atlas PNGs named after `stem`, with a
+/// JSON file saying where each one went, in `out`. Brushes that fail to read,
+/// or are too big for a page, are left out.
+fn process_atlas(brushes: inspect::Selected,
+                 presets: &[abr::BrushPreset],
+                 out: &mut OutputDir,
+                 stem: &str,
+                 options: &cli::ExtractOptions,
+                 entries: &mut Vec<manifest::Entry>)
+                 -> Result<(), Error> {
+    let name = if stem.is_empty() { "atlas" } else { stem };
+
+    let mut brushes_read = vec![];
+    for (idx, offset, brush_result) in brushes {
+        let mut entry = inspect::new_entry(idx, offset);
+        match brush_result {
+            Ok(brush) => {
+                inspect::describe(&mut entry, &brush, presets);
+                // Atlases are 8-bit.
+                let brush = brush.into_image().into_8bit();
+                let preset = presets.iter().find(|p| p.uses(&brush));
+                let brush_name = brush_name(&brush, preset, &format!("{} {}", name, idx));
+                brushes_read.push((entries.len(), idx, brush, brush_name));
+            }
+            Err(e) => {
+                eprintln!("error on brush {}: {}", idx, e);
+                entry.error = Some(e.to_string());
+            }
+        }
+        entries.push(entry);
+    }
+
+    let sprites = brushes_read.iter()
+        .map(|&(_, idx, ref brush, ref brush_name)| {
+            atlas::Sprite {
+                data: &brush.data[..],
+                width: brush.width,
+                height: brush.height,
+                name: brush_name,
+                index: idx,
+            }
+        })
+        .collect::<Vec<_>>();
+    let packed = atlas::pack(&sprites, &options.atlas);
+
+    let page_names = (0..packed.pages.len())
+        .map(|i| format!("{}-{}.png", name, i))
+        .collect::<Vec<_>>();
+    for (&(entry_idx, ..), placement) in brushes_read.iter().zip(&packed.placements) {
+        let entry = &mut entries[entry_idx];
+        match *placement {
+            Some(p) => entry.file_name = Some(page_names[p.page].clone()),
+            None => {
+                eprintln!("error on brush {}: too big for a {}x{} atlas",
+                          entry.index, options.atlas.max_size, options.atlas.max_size);
+                entry.error = Some("too big for the atlas".to_string());
+            }
+        }
+    }
+
+    for (i, (page, page_name)) in packed.pages.iter().zip(&page_names).enumerate() {
+        let save_path = out.path().join(page_name);
+        let saved = out.save(page_name, |path| {
+            png::save_mask(path,
+                           &page.data[..],
+                           page.width,
+                           page.height,
+                           8,
+                           options.png_style,
+                           options.invert)
+                .map_err(|e| Error::CouldntSavePng { file_path: save_path.clone(), err: e })
+        })?;
+        let count = packed.placements.iter().filter(|p| p.map(|p| p.page) == Some(i)).count();
+        report_pack(&saved, count);
+    }
+
+    let file_name = format!("{}.json", name);
+    let save_path = out.path().join(&file_name);
+    let saved = out.save(&file_name, |path| {
+        File::create(path)
+            .and_then(|file| {
+                let mut w = BufWriter::new(file);
+                atlas::write_json(&mut w, &packed, &sprites, &page_names, &options.atlas)?;
+                w.flush()
+            })
+            .map_err(|e| Error::CouldntWriteFile { file_path: save_path.clone(), err: e })
+    })?;
+    saved.report();
+
     Ok(())
 }
 
+/// Prints what happened to a file holding `count` brushes.
+fn report_pack(saved: &Saved, count: usize) {
+    match *saved {
+        Saved::Written(ref path) => println!("Wrote {} ({} brushes).", path.display(), count),
+        Saved::Kept(_) => saved.report(),
+    }
+}
+
+/// Records in the manifest that the brushes that could be read were packed
+/// into the file at `save_path`.
+fn set_file_name(entries: &mut [manifest::Entry], save_path: &Path) {
+    let file_name = save_path.file_name().map(|s| s.to_string_lossy().into_owned());
+    for entry in entries.iter_mut().filter(|entry| entry.error.is_none()) {
+        entry.file_name = file_name.clone();
+    }
+}
+
+/// Name for a brush, from the preset that uses it or the brush itself, or
+/// else `default_name`.
+fn brush_name(brush: &abr::ImageBrush,
+              preset: Option<&abr::BrushPreset>,
+              default_name: &str)
+              -> String {
+    preset.map(|p| p.name.clone())
+        .or_else(|| brush.name.clone())
+        .unwrap_or_else(|| default_name.to_string())
+}
+
+/// Spacing for a brush, as a percentage, from the preset that uses it or
+/// the brush itself.
+fn brush_spacing(brush: &abr::ImageBrush, preset: Option<&abr::BrushPreset>) -> Option<u32> {
+    preset.and_then(|p| p.spacing)
+        .map(|s| s.round() as u32)
+        .or(brush.spacing.map(|s| s as u32))
+}
+
 /// Prints an error, plus some information for humans about what they
 /// might do about it.
 fn report_error(err: Error) {
@@ -95,9 +641,17 @@ fn report_error(err: Error) {
     // Try to suggest how to fix it.
     match err {
         Error::BadCommandlineOptions(_) |
-        Error::WrongNumberOfInputFiles(_) => {
+        Error::NoInputFiles |
+        Error::WrongNumberOfInputFiles(_) |
+        Error::UnknownOutputFormat(_) |
+        Error::BadOptionValue(..) |
+        Error::BadFilenameTemplate(..) |
+        Error::ConflictingOptions(..) => {
             eprintln!("Use -h for help.");
         }
+        Error::CouldntOpenAbr(abr::OpenError::UnsupportedVersion { .. }) => {
+            eprintln!("You can try reading it anyway with --guess-format.");
+        }
         Error::CouldntOpenAbr(_) => {
             eprintln!("Ensure the provided file was an ABR. If it was, \
                        it's unsupported, sorry :-(");
@@ -105,7 +659,11 @@ fn report_error(err: Error) {
         Error::CouldntCreateOutputDir { err: ref io_err, .. }
             if io_err.kind() == io::ErrorKind::AlreadyExists => {
             eprintln!("The output directory will be created. Make sure \
-                       it doesn't already exist.");
+                       it doesn't already exist, or extract into it anyway \
+                       with --force, --merge or --skip-existing.");
+        }
+        Error::OutputFileExists(_) => {
+            eprintln!("Choose another output file with -o.");
         }
         _ => {},
     }
@@ -0,0 +1,103 @@
+//! Writer for JSON manifests describing the brushes in an ABR file.
+//!
+//! A manifest is an object with the path of the ABR it describes and an
+//! entry for each brush, in file order:
+//!
+//! ```json
+//! {
+//!   "source": "mybrushes.abr",
+//!   "brushes": [
+//!     {"index": 0, "filename": "0.png", "width": 64, "height": 64, "depth": 8,
+//!      "compression": "rle", "offset": 16, "name": "Soft Round", "uuid": "...",
+//!      "spacing": 25, "error": null}
+//!   ]
+//! }
+//! ```
+//!
+//! Anything that isn't known about a brush is `null`.
+
+use json;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// What's known about one brush.
+#[derive(Debug, Clone, Default)]
+pub struct Entry {
+    /// Position of the brush in the file.
+    pub index: usize,
+    /// Name of the file the brush was written to.
+    pub file_name: Option<String>,
+    /// Width of the tip.
+    pub width: Option<u32>,
+    /// Height of the tip.
+    pub height: Option<u32>,
+    /// Bit-depth of the tip (8 or 16).
+    pub depth: Option<u16>,
+    /// How the tip was stored: `"raw"`, `"rle"`, or `"computed"` for brushes
+    /// described by their parameters instead of an image.
+    pub compression: Option<&'static str>,
+    /// Offset of the brush in the file.
+    pub offset: Option<u64>,
+    /// The brush's name.
+    pub name: Option<String>,
+    /// The brush's UUID.
+    pub uuid: Option<String>,
+    /// Spacing, as a percentage of the brush size.
+    pub spacing: Option<u32>,
+    /// Why the brush couldn't be read or written, if it couldn't.
+    pub error: Option<String>,
+}
+
+/// Saves a manifest of `entries`, describing the ABR at `source`, to
+/// `path`.
+pub fn save(path: &Path, source: &str, entries: &[Entry]) -> io::Result<()> {
+    let mut w = BufWriter::new(File::create(path)?);
+    write(&mut w, source, entries)?;
+    w.flush()
+}
+
+/// Writes a manifest to `w`. See `save`.
+pub fn write<W: Write>(w: &mut W, source: &str, entries: &[Entry]) -> io::Result<()> {
+    writeln!(w, "{{")?;
+    writeln!(w, "  \"source\": {},", json::string(source))?;
+    write!(w, "  \"brushes\": [")?;
+    for (i, entry) in entries.iter().enumerate() {
+        let sep = if i == 0 { "" } else { "," };
+        write!(w, "{}\n    {}", sep, entry_json(entry))?;
+    }
+    if !entries.is_empty() {
+        write!(w, "\n  ")?;
+    }
+    writeln!(w, "]")?;
+    writeln!(w, "}}")
+}
+
+/// Formats an entry as a one-line JSON object.
+fn entry_json(entry: &Entry) -> String {
+    let fields = [
+        ("index", entry.index.to_string()),
+        ("filename", opt_string(entry.file_name.as_ref().map(|s| &s[..]))),
+        ("width", opt_number(entry.width)),
+        ("height", opt_number(entry.height)),
+        ("depth", opt_number(entry.depth)),
+        ("compression", opt_string(entry.compression)),
+        ("offset", opt_number(entry.offset)),
+        ("name", opt_string(entry.name.as_ref().map(|s| &s[..]))),
+        ("uuid", opt_string(entry.uuid.as_ref().map(|s| &s[..]))),
+        ("spacing", opt_number(entry.spacing)),
+        ("error", opt_string(entry.error.as_ref().map(|s| &s[..]))),
+    ];
+    let fields = fields.iter()
+        .map(|(key, value)| format!("\"{}\": {}", key, value))
+        .collect::<Vec<_>>();
+    format!("{{{}}}", fields.join(", "))
+}
+
+fn opt_string(s: Option<&str>) -> String {
+    s.map(json::string).unwrap_or_else(|| "null".to_string())
+}
+
+fn opt_number<T: ToString>(x: Option<T>) -> String {
+    x.map(|x| x.to_string()).unwrap_or_else(|| "null".to_string())
+}
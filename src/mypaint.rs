@@ -0,0 +1,179 @@
+//! Writing brushes as MyPaint brush packs.
+//!
+//! MyPaint brushes don't have image tips; every dab is a soft ellipse. So
+//! each brush is approximated from the ABR brush's diameter, spacing,
+//! hardness and so on, and the tip image is used as the brush's preview.
+//!
+//! A brush pack is a zip file with a `.myb` (JSON) and `_prev.png` for each
+//! brush, and an `order.conf` putting them in a group. MyPaint imports it
+//! with Brush > Import Brushes.
+
+use json;
+use png;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use util;
+use zip::ZipWriter;
+
+/// One brush to put in a pack.
+///
+/// The optional fields are `None` when the ABR doesn't record them, in which
+/// case MyPaint's defaults are used.
+pub struct Tip<'a> {
+    /// Row-major vector of width×height 8-bit samples, for the preview. 0 is
+    /// transparent.
+    pub data: &'a [u8],
+    /// Image width.
+    pub width: u32,
+    /// Image height.
+    pub height: u32,
+    /// Name of the brush.
+    pub name: &'a str,
+    /// Diameter, in pixels.
+    pub diameter: f64,
+    /// Spacing, as a percentage of the diameter.
+    pub spacing: Option<f64>,
+    /// Hardness, as a percentage.
+    pub hardness: Option<f64>,
+    /// Roundness, as a percentage. 100 is a circle.
+    pub roundness: Option<f64>,
+    /// Angle, in degrees counter-clockwise.
+    pub angle: Option<f64>,
+    /// Whether to paint anti-aliased.
+    pub antialias: Option<bool>,
+}
+
+/// MyPaint's brush previews are this size.
+const PREVIEW_SIZE: u32 = 128;
+
+/// Saves `tips` as a MyPaint brush pack at `path`, with the brushes in the
+/// group `group`.
+pub fn save(path: &Path, group: &str, tips: &[Tip]) -> io::Result<()> {
+    let w = BufWriter::new(File::create(path)?);
+    let mut w = write(w, group, tips)?;
+    w.flush()
+}
+
+/// Writes a MyPaint brush pack to `w`, returning it when done. See `save`.
+pub fn write<W: Write>(w: W, group: &str, tips: &[Tip]) -> io::Result<W> {
+    let mut zip = ZipWriter::new(w);
+
+    // Brushes go in a directory named after the group, so they don't collide
+    // with brushes from other packs once imported.
+    let group = group.replace(['\n', '\r'], " ");
+    let mut dir = util::sanitize_file_name(&group);
+    if dir.is_empty() {
+        dir = "abrupng".to_string();
+    }
+
+    let mut order = format!("Group: {}\n", group);
+    let mut buf = vec![];
+    for (idx, tip) in tips.iter().enumerate() {
+        let brush_name = format!("{}/{:03} {}", dir, idx, util::sanitize_file_name(tip.name));
+
+        zip.add_deflated(&format!("{}.myb", brush_name), to_myb(tip).as_bytes())?;
+
+        buf.clear();
+        let preview = util::thumbnail(tip.data, tip.width, tip.height, PREVIEW_SIZE, PREVIEW_SIZE);
+        png::write(&mut buf, &preview, PREVIEW_SIZE, PREVIEW_SIZE, 8, png::ColorType::Greyscale)
+            .map_err(io::Error::other)?;
+        zip.add_stored(&format!("{}_prev.png", brush_name), &buf)?;
+
+        order.push_str(&brush_name);
+        order.push('\n');
+    }
+    zip.add_deflated("order.conf", order.as_bytes())?;
+
+    zip.finish()
+}
+
+/// Makes the `.myb` (version 3) for a brush.
+pub fn to_myb(tip: &Tip) -> String {
+    // Base values of the settings. Each is clamped to the range MyPaint allows.
+    // Values that aren't finite (presets can hold NaN) fall back to defaults,
+    // since JSON can't represent them.
+    let diameter = if tip.diameter.is_finite() { tip.diameter } else { 1.0 };
+    let radius = (diameter.max(1.0) / 2.0).ln().clamp(-2.0, 6.0);
+    // Spacing is the distance between dabs; MyPaint wants dabs per radius.
+    let dabs_per_radius = tip.spacing
+        .filter(|&s| s.is_finite() && s > 0.0)
+        .map(|s| (50.0 / s).clamp(0.0, 6.0))
+        .unwrap_or(2.0);
+    let hardness = tip.hardness
+        .filter(|h| h.is_finite())
+        .map(|h| (h / 100.0).clamp(0.0, 1.0))
+        .unwrap_or(0.8);
+    let ratio = tip.roundness
+        .filter(|&r| r.is_finite() && r > 0.0)
+        .map(|r| (100.0 / r).clamp(1.0, 10.0))
+        .unwrap_or(1.0);
+    // MyPaint's angle goes clockwise and only covers half a turn, since an
+    // ellipse is symmetric.
+    let angle = tip.angle
+        .filter(|a| a.is_finite())
+        .map(|a| (-a).rem_euclid(180.0))
+        .unwrap_or(90.0);
+    let antialiasing = match tip.antialias {
+        Some(false) => 0.0,
+        _ => 1.0,
+    };
+
+    let settings: [(&str, f64, &str); 8] = [
+        ("anti_aliasing", antialiasing, ""),
+        ("dabs_per_actual_radius", dabs_per_radius, ""),
+        ("dabs_per_basic_radius", 0.0, ""),
+        ("elliptical_dab_angle", angle, ""),
+        ("elliptical_dab_ratio", ratio, ""),
+        ("hardness", hardness, ""),
+        // Let pen pressure control opacity, like Photoshop's default.
+        ("opaque_multiply", 0.0, "\"pressure\": [[0.0, 0.0], [1.0, 1.0]]"),
+        ("radius_logarithmic", radius, ""),
+    ];
+
+    let mut myb = String::new();
+    myb.push_str("{\n");
+    myb.push_str(&format!("    \"comment\": {},\n",
+                          json::string(&format!("{} (converted by abrupng)", tip.name))));
+    myb.push_str("    \"group\": \"\",\n");
+    myb.push_str("    \"parent_brush_name\": \"\",\n");
+    myb.push_str("    \"settings\": {\n");
+    for (i, &(name, base_value, inputs)) in settings.iter().enumerate() {
+        let comma = if i + 1 < settings.len() { "," } else { "" };
+        myb.push_str(&format!("        \"{}\": {{\"base_value\": {}, \"inputs\": {{{}}}}}{}\n",
+                              name,
+                              base_value,
+                              inputs,
+                              comma));
+    }
+    myb.push_str("    },\n");
+    myb.push_str("    \"version\": 3\n");
+    myb.push_str("}\n");
+    myb
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64;
+
+    #[test]
+    fn myb_has_no_non_finite_numbers() {
+        let tip = Tip {
+            data: &[0],
+            width: 1,
+            height: 1,
+            name: "nan",
+            diameter: f64::INFINITY,
+            spacing: Some(f64::NAN),
+            hardness: Some(f64::NAN),
+            roundness: Some(f64::NEG_INFINITY),
+            angle: Some(f64::NAN),
+            antialias: None,
+        };
+        let myb = to_myb(&tip);
+        assert!(!myb.contains("NaN") && !myb.contains("inf"), "{}", myb);
+        assert!(myb.contains("\"hardness\": {\"base_value\": 0.8,"));
+        assert!(myb.contains("\"elliptical_dab_angle\": {\"base_value\": 90,"));
+    }
+}
@@ -0,0 +1,329 @@
+//! Writing files into an output directory without clobbering anything
+//! abrupng didn't write.
+//!
+//! Each output directory gets a `.abrupng` file listing the files abrupng
+//! wrote there. When extracting into a directory that already exists, only
+//! files on that list are ever overwritten or removed. Each file is added to
+//! the list before it's put in place, so the list is still right if abrupng
+//! is stopped partway through.
+
+use cli::ExistingPolicy;
+use err::{Error, OutputError};
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Component, Path, PathBuf};
+
+/// Name of the file listing what abrupng wrote in a directory.
+const LIST_FILE_NAME: &str = ".abrupng";
+
+/// A directory abrupng is writing files into.
+pub struct OutputDir {
+    path: PathBuf,
+    policy: ExistingPolicy,
+    /// Files abrupng wrote here on earlier runs, relative to `path`.
+    previous: HashSet<String>,
+    /// Files written (or kept) this time, in order.
+    written: Vec<String>,
+    /// The same files as `written`, for looking them up.
+    written_set: HashSet<String>,
+    /// The list file, open for adding files to as they're written.
+    list: fs::File,
+    /// Files on the list file so far: `previous`, and any added since.
+    listed: HashSet<String>,
+}
+
+/// What happened to a file passed to `OutputDir::save`.
+pub enum Saved {
+    /// It was written to this path.
+    Written(PathBuf),
+    /// It already existed at this path, and was left alone.
+    Kept(PathBuf),
+}
+
+impl Saved {
+    /// Prints a line saying what happened.
+    pub fn report(&self) {
+        match *self {
+            Saved::Written(ref path) => println!("Wrote {}.", path.display()),
+            Saved::Kept(ref path) => {
+                println!("Skipped {}, which already exists.", path.display())
+            }
+        }
+    }
+}
+
+impl OutputDir {
+    /// Creates the directory at `path`. Unless `policy` allows it, it mustn't
+    /// already exist.
+    pub fn create(path: &Path, policy: ExistingPolicy) -> Result<OutputDir, Error> {
+        let created = if policy == ExistingPolicy::Fail {
+            fs::create_dir(path)
+        } else {
+            fs::create_dir_all(path)
+        };
+        created.map_err(|e| Error::CouldntCreateOutputDir {
+            output_path: path.to_path_buf(),
+            err: e,
+        })?;
+
+        let list_path = path.join(LIST_FILE_NAME);
+        let previous = match fs::File::open(&list_path) {
+            Ok(file) => {
+                BufReader::new(file).lines()
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|e| Error::CouldntOpenFile { file_path: list_path.clone(), err: e })?
+                    .into_iter()
+                    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                    // Never touch anything outside the directory, whatever
+                    // the list says.
+                    .filter(|line| is_plain_relative(line))
+                    .collect()
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => HashSet::new(),
+            Err(e) => return Err(Error::CouldntOpenFile { file_path: list_path, err: e }),
+        };
+
+        let list = open_list(&list_path)
+            .map_err(|e| Error::CouldntWriteFile { file_path: list_path, err: e })?;
+
+        Ok(OutputDir {
+            path: path.to_path_buf(),
+            policy,
+            listed: previous.clone(),
+            previous,
+            written: vec![],
+            written_set: HashSet::new(),
+            list,
+        })
+    }
+
+    /// The directory's path.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Saves a file called `name` (which can include subdirectories) by
+    /// calling `save` with a temporary path to write it to, then renaming
+    /// that into place. If the file already exists, it's skipped or
+    /// overwritten as the policy says, but never overwritten unless abrupng
+    /// wrote it.
+    pub fn save<F, E>(&mut self, name: &str, save: F) -> Result<Saved, E>
+        where F: FnOnce(&Path) -> Result<(), E>,
+              E: From<OutputError>
+    {
+        let path = self.path.join(name);
+        if self.written_set.contains(name) {
+            // Two files with the same name this run.
+            return Err(OutputError::Exists(path).into());
+        }
+        let ours = self.previous.contains(name);
+        if path.exists() {
+            if self.policy == ExistingPolicy::SkipExisting {
+                if ours {
+                    self.add_written(name);
+                }
+                return Ok(Saved::Kept(path));
+            }
+            if !ours {
+                return Err(OutputError::NotOurs(path).into());
+            }
+        }
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(OutputError::from)?;
+        }
+        let file_name = path.file_name().map(|s| s.to_string_lossy().into_owned());
+        let tmp_path = path.with_file_name(format!(".{}.tmp", file_name.unwrap_or_default()));
+        // Create the temporary file here, so one that's already there isn't
+        // truncated.
+        fs::OpenOptions::new().write(true).create_new(true).open(&tmp_path).map_err(|e| {
+            if e.kind() == io::ErrorKind::AlreadyExists {
+                OutputError::NotOurs(tmp_path.clone())
+            } else {
+                OutputError::from(e)
+            }
+        })?;
+        if let Err(e) = save(&tmp_path) {
+            let _ = fs::remove_file(&tmp_path);
+            return Err(e);
+        }
+        // Claim the file before it's there, so it's never there unclaimed.
+        if let Err(e) = self.add_to_list(name).and_then(|()| fs::rename(&tmp_path, &path)) {
+            let _ = fs::remove_file(&tmp_path);
+            return Err(OutputError::from(e).into());
+        }
+
+        self.add_written(name);
+        Ok(Saved::Written(path))
+    }
+
+    fn add_written(&mut self, name: &str) {
+        self.written.push(name.to_string());
+        self.written_set.insert(name.to_string());
+    }
+
+    /// Adds `name` to the end of the list file, unless it's on it already.
+    fn add_to_list(&mut self, name: &str) -> io::Result<()> {
+        if self.listed.contains(name) {
+            return Ok(());
+        }
+        writeln!(self.list, "{}", name)?;
+        self.listed.insert(name.to_string());
+        Ok(())
+    }
+
+    /// Finishes up by rewriting the list of files abrupng wrote, so it has
+    /// just the ones that are still there. With `--force`, files from
+    /// earlier runs that weren't written this time are removed.
+    pub fn finish(mut self) -> Result<(), Error> {
+        let mut stale = self.previous.iter()
+            .filter(|name| !self.written_set.contains(*name))
+            .cloned()
+            .collect::<Vec<_>>();
+        stale.sort();
+
+        for name in stale {
+            let path = self.path.join(&name);
+            if self.policy == ExistingPolicy::Force {
+                match fs::remove_file(&path) {
+                    Ok(()) => {
+                        println!("Removed {}.", path.display());
+                        // Tidy up subdirectories left empty; this fails if
+                        // there's anything else in them.
+                        if let Some(parent) = path.parent().filter(|p| *p != self.path) {
+                            let _ = fs::remove_dir(parent);
+                        }
+                    }
+                    Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+                    Err(e) => eprintln!("warning: couldn't remove {}: {}", path.display(), e),
+                }
+            } else if path.exists() {
+                // Still ours.
+                self.written.push(name);
+            }
+        }
+
+        let list_path = self.path.join(LIST_FILE_NAME);
+        save_list(&list_path, &self.written)
+            .map_err(|e| Error::CouldntWriteFile { file_path: list_path, err: e })
+    }
+}
+
+/// Whether `name` is a relative path made of plain file names, with no
+/// `..` or root, so it can't lead out of the directory it's joined to.
+fn is_plain_relative(name: &str) -> bool {
+    Path::new(name).components().all(|c| matches!(c, Component::Normal(_)))
+}
+
+/// First line of the list file.
+const LIST_HEADER: &str = "# Files written by abrupng. It will only overwrite these.";
+
+/// Opens the list file at `list_path` for adding to, creating it if there
+/// isn't one.
+fn open_list(list_path: &Path) -> io::Result<fs::File> {
+    let mut file = fs::OpenOptions::new().append(true).create(true).open(list_path)?;
+    if file.metadata()?.len() == 0 {
+        writeln!(file, "{}", LIST_HEADER)?;
+    }
+    Ok(file)
+}
+
+/// Writes the list of files abrupng wrote to `list_path`, by way of a
+/// temporary file.
+fn save_list(list_path: &Path, names: &[String]) -> io::Result<()> {
+    let tmp_path = list_path.with_file_name(format!("{}.tmp", LIST_FILE_NAME));
+    {
+        let mut w = io::BufWriter::new(fs::File::create(&tmp_path)?);
+        writeln!(w, "{}", LIST_HEADER)?;
+        for name in names {
+            writeln!(w, "{}", name)?;
+        }
+        w.flush()?;
+    }
+    fs::rename(&tmp_path, list_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::process;
+    use super::*;
+
+    /// A new, empty directory to test in, removed when dropped.
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new(name: &str) -> TestDir {
+            let path = env::temp_dir().join(format!("abrupng-{}-{}", name, process::id()));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            TestDir(path)
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn write_file(path: &Path) -> Result<(), OutputError> {
+        fs::write(path, "new").map_err(OutputError::from)
+    }
+
+    fn read(path: &Path) -> String {
+        fs::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn force_removes_only_listed_files() {
+        let dir = TestDir::new("force");
+        let out = dir.0.join("out");
+        fs::create_dir(&out).unwrap();
+        for name in &["outside.png", "out/old.png", "out/user.png"] {
+            fs::write(dir.0.join(name), "old").unwrap();
+        }
+        fs::write(out.join(LIST_FILE_NAME),
+                  format!("{}\nold.png\n../outside.png\n{}\n",
+                          LIST_HEADER,
+                          dir.0.join("outside.png").display()))
+            .unwrap();
+
+        let mut output = OutputDir::create(&out, ExistingPolicy::Force).unwrap();
+        output.save("new.png", write_file).unwrap();
+        output.finish().unwrap();
+
+        assert!(!out.join("old.png").exists());
+        assert_eq!(read(&out.join("new.png")), "new");
+        assert_eq!(read(&out.join("user.png")), "old");
+        assert_eq!(read(&dir.0.join("outside.png")), "old");
+        let list = read(&out.join(LIST_FILE_NAME));
+        assert_eq!(list.lines().skip(1).collect::<Vec<_>>(), ["new.png"]);
+    }
+
+    #[test]
+    fn force_refuses_to_overwrite_unlisted_files() {
+        let dir = TestDir::new("unlisted");
+        fs::write(dir.0.join("user.png"), "old").unwrap();
+        fs::write(dir.0.join(".other.png.tmp"), "old").unwrap();
+
+        let mut output = OutputDir::create(&dir.0, ExistingPolicy::Force).unwrap();
+        match output.save("user.png", write_file) {
+            Err(OutputError::NotOurs(ref path)) => assert_eq!(*path, dir.0.join("user.png")),
+            _ => panic!("overwrote a file abrupng didn't write"),
+        }
+        // Nor one that happens to have the temporary file's name.
+        match output.save("other.png", write_file) {
+            Err(OutputError::NotOurs(ref path)) => {
+                assert_eq!(*path, dir.0.join(".other.png.tmp"))
+            }
+            _ => panic!("overwrote a file abrupng didn't write"),
+        }
+        output.finish().unwrap();
+
+        assert_eq!(read(&dir.0.join("user.png")), "old");
+        assert_eq!(read(&dir.0.join(".other.png.tmp")), "old");
+        assert!(!dir.0.join("other.png").exists());
+    }
+}
@@ -1,17 +1,203 @@
-use err::SavePngError;
+//! Writing brush images as PNGs, and reading PNGs back in to make brushes
+//! from.
+
 use pnglib;
 use pnglib::HasParameters;
+use std;
 use std::fs::File;
+use std::io::{self, Write};
 use std::path::Path;
 
+quick_error! {
+    /// Error from saving a PNG.
+    #[derive(Debug)]
+    pub enum SavePngError {
+        /// The PNG encoder failed.
+        EncodingError(err: pnglib::EncodingError) {
+            description("couldn't encode png")
+            display("couldn't encode PNG: {}", err)
+            cause(err)
+            from()
+        }
+        /// The file couldn't be written.
+        IoError(err: io::Error) {
+            description("couldn't save PNG")
+            display("couldn't save PNG: {}", err)
+            cause(err)
+            from()
+        }
+        /// PNG can't store greyscale at this bit-depth.
+        BadBitDepth(depth: u16) {
+            description("bad bit-depth")
+            display("bad bit-depth: {}", depth)
+        }
+        /// The image would be too big to hold in memory.
+        TooLarge {
+            description("image too large")
+            display("image too large")
+        }
+    }
+}
+
+quick_error! {
+    /// Error from loading a PNG.
+    #[derive(Debug)]
+    pub enum LoadPngError {
+        /// The PNG decoder failed.
+        DecodingError(err: pnglib::DecodingError) {
+            description("couldn't decode png")
+            display("couldn't decode PNG: {}", err)
+            cause(err)
+            from()
+        }
+        /// The file couldn't be read.
+        IoError(err: io::Error) {
+            description("couldn't read PNG")
+            display("couldn't read PNG: {}", err)
+            cause(err)
+            from()
+        }
+    }
+}
+
+/// Channel layout of the pixels to save.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ColorType {
+    /// One grey channel.
+    Greyscale,
+    /// A grey channel and an alpha channel.
+    GreyscaleAlpha,
+    /// Red, green and blue channels.
+    Rgb,
+    /// Red, green, blue and alpha channels.
+    Rgba,
+}
+
+/// Saves `data`, `width`×`height` greyscale samples of bit-depth `depth`, as
+/// a PNG at `path`. Samples wider than 8 bits are expected to be big-endian.
 pub fn save_greyscale(path: &Path,
                       data: &[u8],
                       width: u32,
                       height: u32,
                       depth: u16)
                       -> Result<(), SavePngError> {
+    save(path, data, width, height, depth, ColorType::Greyscale)
+}
+
+/// How a brush's mask is turned into pixels.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MaskStyle {
+    /// Greyscale, as ABR stores it: white is ink and black is transparent.
+    Greyscale,
+    /// A solid colour, with the mask as its alpha channel. Grey fills are
+    /// written as greyscale+alpha, others as RGBA.
+    Alpha {
+        /// Colour of the ink, as RGB.
+        fill: [u8; 3],
+    },
+}
+
+/// Saves a brush mask, `data`, `width`×`height` samples of bit-depth
+/// `depth` (8 or 16, big-endian), as a PNG at `path` in the given `style`.
+/// With `invert`, the mask is flipped first, so greyscale brushes come out
+/// black on white.
+pub fn save_mask(path: &Path,
+                 data: &[u8],
+                 width: u32,
+                 height: u32,
+                 depth: u16,
+                 style: MaskStyle,
+                 invert: bool)
+                 -> Result<(), SavePngError> {
     let fout = File::create(path)?;
-    let mut enc = pnglib::Encoder::new(fout, width, height);
+    write_mask(fout, data, width, height, depth, style, invert)
+}
+
+/// Writes a brush mask as a PNG to `w`. See `save_mask`.
+pub fn write_mask<W: Write>(w: W,
+                            data: &[u8],
+                            width: u32,
+                            height: u32,
+                            depth: u16,
+                            style: MaskStyle,
+                            invert: bool)
+                            -> Result<(), SavePngError> {
+    let sample_len = match depth {
+        8 => 1,
+        16 => 2,
+        _ => return Err(SavePngError::BadBitDepth(depth)),
+    };
+
+    // Flip a sample of the mask, keeping it big-endian.
+    let flip = |sample: &[u8], out: &mut Vec<u8>| {
+        for &b in sample {
+            out.push(if invert { !b } else { b });
+        }
+    };
+
+    let (pixels, color) = match style {
+        MaskStyle::Greyscale if !invert => {
+            return write(w, data, width, height, depth, ColorType::Greyscale);
+        }
+        MaskStyle::Greyscale => {
+            let mut pixels = Vec::with_capacity(data.len());
+            for sample in data.chunks(sample_len) {
+                flip(sample, &mut pixels);
+            }
+            (pixels, ColorType::Greyscale)
+        }
+        MaskStyle::Alpha { fill } => {
+            let grey = fill[0] == fill[1] && fill[1] == fill[2];
+            let fill = if grey { &fill[..1] } else { &fill[..] };
+            let mut pixels = Vec::with_capacity(data.len() * (fill.len() + 1));
+            for sample in data.chunks(sample_len) {
+                for &c in fill {
+                    // Scaling an 8-bit value to 16-bit repeats its byte.
+                    pixels.extend(std::iter::repeat_n(c, sample_len));
+                }
+                flip(sample, &mut pixels);
+            }
+            (pixels, if grey { ColorType::GreyscaleAlpha } else { ColorType::Rgba })
+        }
+    };
+    write(w, &pixels, width, height, depth, color)
+}
+
+/// Saves `data`, `width`×`height` interleaved pixels of type `color` with
+/// `depth` bits per sample, as a PNG at `path`. Samples wider than 8 bits are
+/// expected to be big-endian. Only greyscale may be less than 8-bit.
+pub fn save(path: &Path,
+            data: &[u8],
+            width: u32,
+            height: u32,
+            depth: u16,
+            color: ColorType)
+            -> Result<(), SavePngError> {
+    let fout = File::create(path)?;
+    write(fout, data, width, height, depth, color)
+}
+
+/// Writes a PNG to `w`. See `save`.
+pub fn write<W: Write>(w: W,
+                       data: &[u8],
+                       width: u32,
+                       height: u32,
+                       depth: u16,
+                       color: ColorType)
+                       -> Result<(), SavePngError> {
+    write_with_text(w, data, width, height, depth, color, &[])
+}
+
+/// Writes a PNG to `w`, with a `tEXt` chunk for each keyword/text pair in
+/// `text`. Both should be Latin-1; keywords are 1 to 79 characters.
+pub fn write_with_text<W: Write>(w: W,
+                                 data: &[u8],
+                                 width: u32,
+                                 height: u32,
+                                 depth: u16,
+                                 color: ColorType,
+                                 text: &[(&str, &str)])
+                                 -> Result<(), SavePngError> {
     let bit_depth = match depth {
         1 => pnglib::BitDepth::One,
         2 => pnglib::BitDepth::Two,
@@ -20,8 +206,109 @@ pub fn save_greyscale(path: &Path,
         16 => pnglib::BitDepth::Sixteen,
         _ => return Err(SavePngError::BadBitDepth(depth)),
     };
-    enc.set(pnglib::ColorType::Grayscale).set(bit_depth);
+    if depth < 8 && color != ColorType::Greyscale {
+        return Err(SavePngError::BadBitDepth(depth));
+    }
+    let color_type = match color {
+        ColorType::Greyscale => pnglib::ColorType::Grayscale,
+        ColorType::GreyscaleAlpha => pnglib::ColorType::GrayscaleAlpha,
+        ColorType::Rgb => pnglib::ColorType::RGB,
+        ColorType::Rgba => pnglib::ColorType::RGBA,
+    };
+
+    let mut enc = pnglib::Encoder::new(w, width, height);
+    enc.set(color_type).set(bit_depth);
     let mut writer = enc.write_header()?;
+    for &(keyword, value) in text {
+        let mut chunk = Vec::with_capacity(keyword.len() + 1 + value.len());
+        chunk.extend_from_slice(keyword.as_bytes());
+        chunk.push(0);
+        chunk.extend_from_slice(value.as_bytes());
+        writer.write_chunk(*b"tEXt", &chunk)?;
+    }
     writer.write_image_data(data)?;
     Ok(())
 }
+
+/// An 8-bit image loaded from a PNG.
+#[derive(Debug)]
+pub struct Image {
+    /// Image width.
+    pub width: u32,
+    /// Image height.
+    pub height: u32,
+    /// Channel layout of the pixels.
+    pub color: ColorType,
+    /// Row-major vector of width×height interleaved pixels.
+    pub data: Vec<u8>,
+}
+
+/// Which part of an image becomes a brush tip's mask.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MaskSource {
+    /// The image's brightness: white paints and black is transparent, which
+    /// is how abrupng saves brushes as greyscale PNGs. Transparent pixels
+    /// are treated as black.
+    Luminance,
+    /// The alpha channel. Images without one are opaque everywhere.
+    Alpha,
+}
+
+/// Loads the PNG at `path` as an 8-bit image. Palettes and low bit-depths are
+/// expanded and 16-bit samples are scaled down.
+pub fn load(path: &Path) -> Result<Image, LoadPngError> {
+    let fin = File::open(path)?;
+    let (info, mut reader) = pnglib::Decoder::new(fin).read_info()?;
+    let mut data = vec![0; info.buffer_size()];
+    reader.next_frame(&mut data)?;
+
+    let color = match info.color_type {
+        pnglib::ColorType::Grayscale => ColorType::Greyscale,
+        pnglib::ColorType::GrayscaleAlpha => ColorType::GreyscaleAlpha,
+        pnglib::ColorType::RGB => ColorType::Rgb,
+        // The decoder expands palettes, so this can't really happen.
+        pnglib::ColorType::Indexed => ColorType::Rgb,
+        pnglib::ColorType::RGBA => ColorType::Rgba,
+    };
+
+    Ok(Image { width: info.width, height: info.height, color, data })
+}
+
+impl Image {
+    /// Turns the image into a width×height brush mask (255 paints, 0 is
+    /// transparent), taken from `source`. With `invert`, the mask is flipped,
+    /// eg. so that dark ink on a light background paints. Inverting the
+    /// luminance still leaves transparent pixels transparent.
+    pub fn to_mask(&self, source: MaskSource, invert: bool) -> Vec<u8> {
+        let channels = match self.color {
+            ColorType::Greyscale => 1,
+            ColorType::GreyscaleAlpha => 2,
+            ColorType::Rgb => 3,
+            ColorType::Rgba => 4,
+        };
+        self.data.chunks(channels)
+            .map(|px| {
+                let (luma, alpha) = match self.color {
+                    ColorType::Greyscale => (px[0] as u32, 255),
+                    ColorType::GreyscaleAlpha => (px[0] as u32, px[1] as u32),
+                    ColorType::Rgb | ColorType::Rgba => {
+                        // Rec. 601 luma.
+                        let luma = (299 * px[0] as u32 + 587 * px[1] as u32 +
+                                    114 * px[2] as u32 + 500) / 1000;
+                        let alpha = if channels == 4 { px[3] as u32 } else { 255 };
+                        (luma, alpha)
+                    }
+                };
+                match source {
+                    // Invert before applying alpha, so transparent pixels
+                    // stay transparent.
+                    MaskSource::Luminance => {
+                        let luma = if invert { 255 - luma } else { luma };
+                        ((luma * alpha + 127) / 255) as u8
+                    }
+                    MaskSource::Alpha => if invert { 255 - alpha as u8 } else { alpha as u8 },
+                }
+            })
+            .collect()
+    }
+}
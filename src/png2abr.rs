@@ -0,0 +1,78 @@
+//! Building an ABR file out of a directory of images.
+
+use abrupng::{abr, png};
+use cli::Png2AbrOptions;
+use err::Error;
+use std::fs;
+use std::path::Path;
+
+/// Reads the PNGs in `input_dir`, in file name order, and writes them as
+/// the brushes of an ABR file at `output_path`. Each brush is named after
+/// its file. Images that can't be read are skipped.
+pub fn png2abr(input_dir: &Path, output_path: &Path, options: &Png2AbrOptions)
+               -> Result<(), Error> {
+    // Don't clobber anything.
+    if output_path.exists() {
+        return Err(Error::OutputFileExists(output_path.to_path_buf()));
+    }
+
+    let entries = fs::read_dir(input_dir)
+        .map_err(|e| Error::CouldntReadDir {
+            dir_path: input_dir.to_path_buf(),
+            err: e,
+        })?;
+    let mut paths = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension()
+                .map(|ext| ext.eq_ignore_ascii_case("png"))
+                .unwrap_or(false)
+        })
+        .collect::<Vec<_>>();
+    paths.sort();
+
+    let mut brushes = vec![];
+    for path in &paths {
+        match load_brush(path, options) {
+            Ok(brush) => {
+                println!("Read {}.", path.display());
+                brushes.push(brush);
+            }
+            Err(e) => eprintln!("error on {}: {}", path.display(), e),
+        }
+    }
+    if brushes.is_empty() {
+        return Err(Error::NoImagesFound(input_dir.to_path_buf()));
+    }
+
+    abr::save(output_path, &brushes, &abr::WriteOptions::default())
+        .map_err(|e| Error::CouldntWriteFile {
+            file_path: output_path.to_path_buf(),
+            err: e,
+        })?;
+    println!("Wrote {} ({} brushes).", output_path.display(), brushes.len());
+
+    Ok(())
+}
+
+/// Loads the image at `path` as a brush.
+fn load_brush(path: &Path, options: &Png2AbrOptions) -> Result<abr::ImageBrush, png::LoadPngError> {
+    let image = png::load(path)?;
+
+    let source = options.mask.unwrap_or(match image.color {
+        png::ColorType::GreyscaleAlpha | png::ColorType::Rgba => png::MaskSource::Alpha,
+        png::ColorType::Greyscale | png::ColorType::Rgb => png::MaskSource::Luminance,
+    });
+
+    Ok(abr::ImageBrush {
+        width: image.width,
+        height: image.height,
+        depth: 8,
+        data: image.to_mask(source, options.invert),
+        uuid: None,
+        name: path.file_stem().map(|stem| stem.to_string_lossy().into_owned()),
+        spacing: Some(options.spacing),
+        antialias: None,
+        compressed: false,
+    })
+}
@@ -0,0 +1,212 @@
+//! Contact sheets: every brush in a file laid out in one image, to see them
+//! at a glance.
+//!
+//! Each brush gets a square cell, with its tip scaled down to fit (keeping
+//! its aspect ratio) and drawn as black ink on white. Under each cell is the
+//! brush's index. Brushes that couldn't be read get a cell with a cross in
+//! it, so the indices still line up with the file.
+
+use png::{self, ColorType, SavePngError};
+use std::convert::TryFrom;
+use std::io::Write;
+use std::path::Path;
+use util;
+
+/// How to lay out a contact sheet.
+#[derive(Debug, Clone)]
+pub struct SheetParams {
+    /// Width and height of the area for each brush's tip.
+    pub cell_size: u32,
+    /// Number of cells across. `None` makes the sheet roughly square.
+    pub columns: Option<u32>,
+    /// Label each cell with the brush's index.
+    pub labels: bool,
+}
+
+impl Default for SheetParams {
+    fn default() -> SheetParams {
+        SheetParams { cell_size: 128, columns: None, labels: true }
+    }
+}
+
+/// A cell of a contact sheet.
+///
+/// Tips are scaled down as soon as their cell is made, so a sheet of a big
+/// file doesn't need every full-size tip in memory at once.
+pub enum Cell {
+    /// A brush tip, already scaled to the cell size.
+    Tip(Vec<u8>),
+    /// A brush that couldn't be read.
+    Failed,
+}
+
+impl Cell {
+    /// Makes a cell for an 8-bit brush tip (`width`×`height` samples, 0 is
+    /// transparent), for a sheet laid out with `params`.
+    pub fn tip(data: &[u8], width: u32, height: u32, params: &SheetParams) -> Cell {
+        let cell_size = params.cell_size.max(1);
+        Cell::Tip(util::thumbnail(data, width, height, cell_size, cell_size))
+    }
+}
+
+/// Grey for the lines between cells.
+const GRID_GREY: u8 = 192;
+/// Grey for the cross marking a brush that couldn't be read.
+const FAILED_GREY: u8 = 128;
+
+/// A 3×5 font for the digits 0–9, one row per byte, most significant of
+/// the low 3 bits leftmost.
+const DIGITS: [[u8; 5]; 10] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b011, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b010, 0b010, 0b010],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+];
+
+/// Renders a contact sheet of `cells`, which must have been made with the
+/// same `params`, as an 8-bit greyscale image. Returns the samples, width
+/// and height. Fails if the sheet would be too big.
+pub fn render(cells: &[Cell], params: &SheetParams) -> Result<(Vec<u8>, u32, u32), SavePngError> {
+    let cell_size = params.cell_size.max(1);
+    let count = (cells.len() as u32).max(1);
+    let columns = params.columns
+        .unwrap_or_else(|| (count as f64).sqrt().ceil() as u32)
+        .clamp(1, count);
+    let rows = count.div_ceil(columns);
+
+    // Labels are drawn at a scale that suits the cell size, with a margin of
+    // one (scaled) pixel around them.
+    let font_scale = (cell_size / 64).max(1);
+    let label_height = if params.labels { 7 * font_scale } else { 0 };
+
+    // Cells are separated (and surrounded) by 1px lines.
+    let pitch_x = cell_size as u64 + 1;
+    let pitch_y = cell_size as u64 + label_height as u64 + 1;
+    let width = u32::try_from(columns as u64 * pitch_x + 1);
+    let height = u32::try_from(rows as u64 * pitch_y + 1);
+    let (width, height) = match (width, height) {
+        (Ok(width), Ok(height)) => (width, height),
+        _ => return Err(SavePngError::TooLarge),
+    };
+    // Every offset into the sheet is less than its size, so once that's
+    // known to fit, they can be worked out in usize without overflowing.
+    let size = (width as usize).checked_mul(height as usize).ok_or(SavePngError::TooLarge)?;
+
+    let stride = width as usize;
+    let (cell_size, font_scale) = (cell_size as usize, font_scale as usize);
+    let (pitch_x, pitch_y) = (pitch_x as usize, pitch_y as usize);
+    let mut data = vec![255; size];
+    for x in 0..stride {
+        for row in 0..rows as usize + 1 {
+            data[row * pitch_y * stride + x] = GRID_GREY;
+        }
+    }
+    for y in 0..height as usize {
+        for col in 0..columns as usize + 1 {
+            data[y * stride + col * pitch_x] = GRID_GREY;
+        }
+    }
+
+    for (idx, cell) in cells.iter().enumerate() {
+        let x0 = (idx % columns as usize) * pitch_x + 1;
+        let y0 = (idx / columns as usize) * pitch_y + 1;
+
+        match *cell {
+            // Tips made for another cell size are left blank.
+            Cell::Tip(ref thumb) if thumb.len() == cell_size * cell_size => {
+                for y in 0..cell_size {
+                    let src = &thumb[y * cell_size..][..cell_size];
+                    let start = (y0 + y) * stride + x0;
+                    data[start..start + cell_size].copy_from_slice(src);
+                }
+            }
+            Cell::Tip(_) => (),
+            Cell::Failed => {
+                // A cross from corner to corner, inset a little.
+                let inset = cell_size / 8;
+                for i in inset..cell_size - inset {
+                    for &x in &[i, cell_size - 1 - i] {
+                        data[(y0 + i) * stride + x0 + x] = FAILED_GREY;
+                    }
+                }
+            }
+        }
+
+        if params.labels {
+            draw_number(&mut data, stride, idx, x0 + font_scale, y0 + cell_size + font_scale,
+                        font_scale, cell_size - font_scale);
+        }
+    }
+
+    Ok((data, width, height))
+}
+
+/// Draws `n` in black at (`x0`, `y0`), with each font pixel `scale`
+/// pixels square. Digits that would go past `max_width` are left off.
+fn draw_number(data: &mut [u8], width: usize, n: usize, x0: usize, y0: usize, scale: usize,
+               max_width: usize) {
+    let mut x = x0;
+    for digit in n.to_string().bytes().map(|b| (b - b'0') as usize) {
+        if x + 3 * scale > x0 + max_width {
+            break;
+        }
+        for (row, bits) in DIGITS[digit].iter().enumerate() {
+            for col in 0..3 {
+                if bits & (0b100 >> col) == 0 {
+                    continue;
+                }
+                for dy in 0..scale {
+                    let y = y0 + row * scale + dy;
+                    let start = y * width + x + col * scale;
+                    for px in &mut data[start..start + scale] {
+                        *px = 0;
+                    }
+                }
+            }
+        }
+        x += 4 * scale;
+    }
+}
+
+/// Saves a contact sheet of `cells` as a PNG at `path`.
+pub fn save(path: &Path, cells: &[Cell], params: &SheetParams) -> Result<(), SavePngError> {
+    let (data, width, height) = render(cells, params)?;
+    png::save(path, &data, width, height, 8, ColorType::Greyscale)
+}
+
+/// Writes a contact sheet of `cells` as a PNG to `w`. See `save`.
+pub fn write<W: Write>(w: W, cells: &[Cell], params: &SheetParams) -> Result<(), SavePngError> {
+    let (data, width, height) = render(cells, params)?;
+    png::write(w, &data, width, height, 8, ColorType::Greyscale)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_lays_out_cells() {
+        let params = SheetParams { cell_size: 8, columns: Some(2), labels: false };
+        let cells = vec![Cell::Tip(vec![0; 64]), Cell::Failed, Cell::Failed];
+        let (data, width, height) = render(&cells, &params).unwrap();
+        assert_eq!((width, height), (19, 19));
+        assert_eq!(data.len(), 19 * 19);
+        assert_eq!(data[19 + 1], 0);
+        assert_eq!(data[0], GRID_GREY);
+    }
+
+    #[test]
+    fn render_refuses_huge_sheets() {
+        let params = SheetParams { cell_size: u32::MAX, columns: None, labels: true };
+        match render(&[Cell::Failed], &params) {
+            Err(SavePngError::TooLarge) => (),
+            _ => panic!("rendered a sheet too big to address"),
+        }
+    }
+}
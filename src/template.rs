@@ -0,0 +1,320 @@
+//! Templates for naming the files brushes are written to, like
+//! `{index:04} {name}`.
+
+use abrupng::{filename, manifest};
+use err::Error;
+use std::collections::HashSet;
+
+/// A parsed filename template.
+#[derive(Debug, Clone)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone)]
+enum Part {
+    Literal(String),
+    Field { field: Field, width: usize, zero_pad: bool },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Index,
+    Name,
+    Uuid,
+    Width,
+    Height,
+    Stem,
+}
+
+impl Field {
+    fn from_name(name: &str) -> Option<Field> {
+        Some(match name {
+            "index" => Field::Index,
+            "name" => Field::Name,
+            "uuid" => Field::Uuid,
+            "width" => Field::Width,
+            "height" => Field::Height,
+            "stem" => Field::Stem,
+            _ => return None,
+        })
+    }
+
+    fn is_number(self) -> bool {
+        match self {
+            Field::Index | Field::Width | Field::Height => true,
+            Field::Name | Field::Uuid | Field::Stem => false,
+        }
+    }
+}
+
+impl Default for Template {
+    /// The template `{index}`, which names files `0.png`, `1.png`, etc.
+    fn default() -> Template {
+        Template { parts: vec![Part::Field { field: Field::Index, width: 0, zero_pad: false }] }
+    }
+}
+
+impl Template {
+    /// Parses a template. Placeholders are written in braces: `{index}`,
+    /// `{name}`, `{uuid}`, `{width}`, `{height}` and `{stem}`. Numbers can
+    /// be padded to a width, with zeroes if it starts with one, eg.
+    /// `{index:04}`. `{{` and `}}` stand for literal braces.
+    pub fn parse(s: &str) -> Result<Template, Error> {
+        let bad = |reason: &str| Error::BadFilenameTemplate(s.to_string(), reason.to_string());
+
+        let mut parts = vec![];
+        let mut literal = String::new();
+        let mut chars = s.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '}' => return Err(bad("unmatched }")),
+                '{' => {
+                    let mut placeholder = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => placeholder.push(c),
+                            None => return Err(bad("unclosed {")),
+                        }
+                    }
+
+                    let mut split = placeholder.splitn(2, ':');
+                    let name = split.next().unwrap_or("");
+                    let field = Field::from_name(name)
+                        .ok_or_else(|| bad(&format!("unknown placeholder {{{}}}", name)))?;
+                    let (width, zero_pad) = match split.next() {
+                        None => (0, false),
+                        Some(spec) => {
+                            if !field.is_number() {
+                                return Err(bad(&format!("{{{}}} can't be padded", name)));
+                            }
+                            match spec.parse::<usize>() {
+                                Ok(width) if width <= 64 => (width, spec.starts_with('0')),
+                                _ => return Err(bad(&format!("bad width {}", spec))),
+                            }
+                        }
+                    };
+
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(literal.clone()));
+                        literal.clear();
+                    }
+                    parts.push(Part::Field { field, width, zero_pad });
+                }
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+
+        Ok(Template { parts })
+    }
+
+    /// Fills in the template for the brush described by `entry`, which was
+    /// read from a file called `stem`. Anything the brush doesn't have is
+    /// left empty.
+    pub fn render(&self, entry: &manifest::Entry, stem: &str) -> String {
+        let mut out = String::new();
+        for part in &self.parts {
+            match *part {
+                Part::Literal(ref s) => out.push_str(s),
+                Part::Field { field, width, zero_pad } => {
+                    let number = match field {
+                        Field::Index => Some(entry.index as u64),
+                        Field::Width => entry.width.map(|x| x as u64),
+                        Field::Height => entry.height.map(|x| x as u64),
+                        Field::Name => {
+                            out.push_str(entry.name.as_ref().map(|s| &s[..]).unwrap_or(""));
+                            continue;
+                        }
+                        Field::Uuid => {
+                            out.push_str(entry.uuid.as_ref().map(|s| &s[..]).unwrap_or(""));
+                            continue;
+                        }
+                        Field::Stem => {
+                            out.push_str(stem);
+                            continue;
+                        }
+                    };
+                    if let Some(x) = number {
+                        if zero_pad {
+                            out.push_str(&format!("{:0width$}", x, width = width));
+                        } else {
+                            out.push_str(&format!("{:width$}", x, width = width));
+                        }
+                    }
+                }
+            }
+        }
+        out
+    }
+}
+
+/// Names the files brushes are written to from a template, making sure the
+/// names are safe and no two are the same.
+pub struct FileNamer<'a> {
+    template: &'a Template,
+    stem: &'a str,
+    extension: &'a str,
+    /// Names given out so far, lowercased, since some filesystems ignore
+    /// case.
+    used: HashSet<String>,
+}
+
+impl<'a> FileNamer<'a> {
+    pub fn new(template: &'a Template, stem: &'a str, extension: &'a str) -> FileNamer<'a> {
+        FileNamer { template, stem, extension, used: HashSet::new() }
+    }
+
+    /// Gets a file name for the brush described by `entry`. If the template
+    /// gives a name that's already been used, a number is put on the end.
+    pub fn file_name(&mut self, entry: &manifest::Entry) -> String {
+        let mut base = sanitize(&self.template.render(entry, self.stem));
+        if base.is_empty() {
+            base = entry.index.to_string();
+        }
+
+        let mut name = format!("{}.{}", base, self.extension);
+        let mut n = 1;
+        while !self.used.insert(name.to_lowercase()) {
+            n += 1;
+            name = format!("{}-{}.{}", base, n, self.extension);
+        }
+        name
+    }
+}
+
+/// Maximum length of a file name, in bytes, before the extension.
+const MAX_NAME_LEN: usize = 200;
+
+/// Makes `name` safe to use as a file name on any common filesystem:
+/// path separators, characters Windows doesn't allow and control characters
+/// are replaced with `_`, leading and trailing dots and spaces are trimmed,
+/// names Windows reserves for devices get a `_` on the end, and it's cut
+/// short if it's very long.
+fn sanitize(name: &str) -> String {
+    let mut out = filename::replace_unsafe_chars(name);
+
+    if out.len() > MAX_NAME_LEN {
+        let mut end = MAX_NAME_LEN;
+        while !out.is_char_boundary(end) {
+            end -= 1;
+        }
+        out.truncate(end);
+    }
+    let mut out = out.trim_matches(|c| c == '.' || c == ' ').to_string();
+
+    const RESERVED: &[&str] = &[
+        "CON", "PRN", "AUX", "NUL",
+        "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+        "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+    ];
+    if RESERVED.iter().any(|r| r.eq_ignore_ascii_case(&out)) {
+        out.push('_');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use abrupng::manifest;
+    use super::*;
+
+    fn entry(index: usize, name: Option<&str>) -> manifest::Entry {
+        manifest::Entry {
+            index,
+            width: Some(30),
+            height: Some(7),
+            name: name.map(|s| s.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn render(template: &str, entry: &manifest::Entry) -> String {
+        Template::parse(template).unwrap().render(entry, "stem")
+    }
+
+    fn parse_error(template: &str) -> String {
+        match Template::parse(template) {
+            Err(Error::BadFilenameTemplate(_, reason)) => reason,
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("{} parsed", template),
+        }
+    }
+
+    #[test]
+    fn escaped_braces() {
+        assert_eq!(render("{{{index}}}", &entry(3, None)), "{3}");
+        assert_eq!(render("a{{b}}c", &entry(3, None)), "a{b}c");
+        assert_eq!(parse_error("a}b"), "unmatched }");
+        assert_eq!(parse_error("a{index"), "unclosed {");
+    }
+
+    #[test]
+    fn placeholders() {
+        let e = entry(12, Some("Soft Round"));
+        assert_eq!(render("{stem} {index} {name} {width}x{height}", &e),
+                   "stem 12 Soft Round 30x7");
+        // Anything the brush doesn't have is left empty.
+        assert_eq!(render("[{name}][{uuid}]", &entry(0, None)), "[][]");
+    }
+
+    #[test]
+    fn unknown_placeholder() {
+        assert_eq!(parse_error("{size}"), "unknown placeholder {size}");
+        assert_eq!(parse_error("{}"), "unknown placeholder {}");
+    }
+
+    #[test]
+    fn padding() {
+        assert_eq!(render("{index:04}", &entry(7, None)), "0007");
+        assert_eq!(render("{index:4}", &entry(7, None)), "   7");
+        assert_eq!(render("{index:02}", &entry(1234, None)), "1234");
+        assert_eq!(parse_error("{name:10}"), "{name} can't be padded");
+        assert_eq!(parse_error("{index:x}"), "bad width x");
+        assert_eq!(parse_error("{index:65}"), "bad width 65");
+    }
+
+    #[test]
+    fn collisions_ignore_case() {
+        let template = Template::parse("{name}").unwrap();
+        let mut namer = FileNamer::new(&template, "stem", "png");
+        assert_eq!(namer.file_name(&entry(0, Some("Brush"))), "Brush.png");
+        assert_eq!(namer.file_name(&entry(1, Some("brush"))), "brush-2.png");
+        assert_eq!(namer.file_name(&entry(2, Some("BRUSH"))), "BRUSH-3.png");
+        // An empty name falls back to the index.
+        assert_eq!(namer.file_name(&entry(3, None)), "3.png");
+    }
+
+    #[test]
+    fn reserved_names() {
+        let template = Template::parse("{name}").unwrap();
+        let mut namer = FileNamer::new(&template, "stem", "png");
+        assert_eq!(namer.file_name(&entry(0, Some("CON"))), "CON_.png");
+        assert_eq!(namer.file_name(&entry(1, Some("lpt1"))), "lpt1_.png");
+        assert_eq!(namer.file_name(&entry(2, Some("CONSOLE"))), "CONSOLE.png");
+        assert_eq!(namer.file_name(&entry(3, Some(" ..a/b:c.. "))), "a_b_c.png");
+    }
+
+    #[test]
+    fn long_names_are_cut_on_a_char_boundary() {
+        // 'é' is two bytes, so byte 200 falls in the middle of one.
+        let name = format!("a{}", "é".repeat(150));
+        let template = Template::parse("{name}").unwrap();
+        let mut namer = FileNamer::new(&template, "stem", "png");
+        let file_name = namer.file_name(&entry(0, Some(&name)));
+        let base = file_name.trim_end_matches(".png");
+        assert_eq!(base.len(), 199);
+        assert!(name.starts_with(base));
+    }
+}
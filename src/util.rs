@@ -0,0 +1,52 @@
+//! Helpers shared by the brush writers.
+
+use filename;
+
+/// Renders an 8-bit brush tip (`data`, `tip_width`×`tip_height` samples, 0
+/// is transparent) as black ink on white, centred in a `width`×`height`
+/// image. Tips bigger than that are scaled down to fit, keeping their aspect
+/// ratio.
+pub fn thumbnail(data: &[u8], tip_width: u32, tip_height: u32, width: u32, height: u32)
+                 -> Vec<u8> {
+    let (tw, th) = (tip_width.max(1), tip_height.max(1));
+    let scale = (width as f64 / tw as f64).min(height as f64 / th as f64).min(1.0);
+    let sw = ((tw as f64 * scale).round() as u32).clamp(1, width);
+    let sh = ((th as f64 * scale).round() as u32).clamp(1, height);
+    let x0 = (width - sw) / 2;
+    let y0 = (height - sh) / 2;
+
+    let mut out = vec![255; (width * height) as usize];
+    if data.len() < (tip_width as usize) * (tip_height as usize) {
+        return out;
+    }
+    for y in 0..sh {
+        // Average the block of source pixels that lands on each output pixel.
+        let (sy0, sy1) = (y * th / sh, ((y + 1) * th / sh).max(y * th / sh + 1));
+        for x in 0..sw {
+            let (sx0, sx1) = (x * tw / sw, ((x + 1) * tw / sw).max(x * tw / sw + 1));
+            let mut total = 0u64;
+            for sy in sy0..sy1.min(tip_height) {
+                let row = &data[(sy * tip_width) as usize..][..tip_width as usize];
+                total += row[sx0 as usize..sx1.min(tip_width) as usize]
+                    .iter()
+                    .map(|&v| v as u64)
+                    .sum::<u64>();
+            }
+            let count = ((sy1 - sy0) * (sx1 - sx0)) as u64;
+            out[((y0 + y) * width + x0 + x) as usize] = 255 - (total / count) as u8;
+        }
+    }
+    out
+}
+
+/// Turns a brush name into something safe to use as a file name inside an
+/// archive. This is kept to ASCII, which every tool reading the archive will
+/// agree on.
+pub fn sanitize_file_name(name: &str) -> String {
+    let name = filename::replace_unsafe_chars(name)
+        .chars()
+        .map(|c| if c.is_ascii() { c } else { '_' })
+        .take(64)
+        .collect::<String>();
+    name.trim().to_string()
+}
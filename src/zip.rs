@@ -0,0 +1,174 @@
+//! Minimal writer for zip archives, enough for resource bundles.
+
+use deflate;
+use std::io::{self, Write};
+
+/// A zip archive being written to `W`.
+pub struct ZipWriter<W: Write> {
+    w: W,
+    offset: u64,
+    entries: Vec<Entry>,
+}
+
+/// What the central directory needs to know about a written file.
+struct Entry {
+    name: String,
+    method: u16,
+    crc: u32,
+    compressed_size: u32,
+    size: u32,
+    offset: u32,
+}
+
+// Compression methods.
+const STORED: u16 = 0;
+const DEFLATED: u16 = 8;
+
+// Every entry gets the same modification time (1980-01-01 00:00), which
+// keeps the output reproducible.
+const DOS_TIME: u16 = 0;
+const DOS_DATE: u16 = (1 << 5) | 1;
+
+impl<W: Write> ZipWriter<W> {
+    /// Starts an archive written to `w`.
+    pub fn new(w: W) -> ZipWriter<W> {
+        ZipWriter { w, offset: 0, entries: vec![] }
+    }
+
+    /// Adds a file without compressing it.
+    pub fn add_stored(&mut self, name: &str, data: &[u8]) -> io::Result<()> {
+        self.add(name, STORED, data, data)
+    }
+
+    /// Adds a file, deflating it.
+    pub fn add_deflated(&mut self, name: &str, data: &[u8]) -> io::Result<()> {
+        let compressed = deflate::deflate_bytes(data);
+        self.add(name, DEFLATED, data, &compressed)
+    }
+
+    fn add(&mut self, name: &str, method: u16, data: &[u8], compressed: &[u8]) -> io::Result<()> {
+        let entry = Entry {
+            name: name.to_string(),
+            method,
+            crc: crc32(data),
+            compressed_size: to_u32(compressed.len() as u64)?,
+            size: to_u32(data.len() as u64)?,
+            offset: to_u32(self.offset)?,
+        };
+
+        let mut header = vec![];
+        put_u32(&mut header, 0x04034b50);
+        put_u16(&mut header, 20); // version needed to extract
+        put_u16(&mut header, 1 << 11); // flags: name is UTF-8
+        put_u16(&mut header, entry.method);
+        put_u16(&mut header, DOS_TIME);
+        put_u16(&mut header, DOS_DATE);
+        put_u32(&mut header, entry.crc);
+        put_u32(&mut header, entry.compressed_size);
+        put_u32(&mut header, entry.size);
+        put_u16(&mut header, entry.name.len() as u16);
+        put_u16(&mut header, 0); // extra field length
+        header.extend_from_slice(entry.name.as_bytes());
+
+        self.w.write_all(&header)?;
+        self.w.write_all(compressed)?;
+        self.offset += (header.len() + compressed.len()) as u64;
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    /// Writes the central directory, finishing the archive.
+    pub fn finish(mut self) -> io::Result<W> {
+        let dir_offset = to_u32(self.offset)?;
+        let mut dir = vec![];
+        for entry in &self.entries {
+            put_u32(&mut dir, 0x02014b50);
+            put_u16(&mut dir, 20); // version made by
+            put_u16(&mut dir, 20); // version needed to extract
+            put_u16(&mut dir, 1 << 11);
+            put_u16(&mut dir, entry.method);
+            put_u16(&mut dir, DOS_TIME);
+            put_u16(&mut dir, DOS_DATE);
+            put_u32(&mut dir, entry.crc);
+            put_u32(&mut dir, entry.compressed_size);
+            put_u32(&mut dir, entry.size);
+            put_u16(&mut dir, entry.name.len() as u16);
+            put_u16(&mut dir, 0); // extra field length
+            put_u16(&mut dir, 0); // comment length
+            put_u16(&mut dir, 0); // disk number
+            put_u16(&mut dir, 0); // internal attributes
+            put_u32(&mut dir, 0); // external attributes
+            put_u32(&mut dir, entry.offset);
+            dir.extend_from_slice(entry.name.as_bytes());
+        }
+
+        let dir_size = to_u32(dir.len() as u64)?;
+        let count = self.entries.len() as u16;
+        put_u32(&mut dir, 0x06054b50);
+        put_u16(&mut dir, 0); // this disk
+        put_u16(&mut dir, 0); // disk with central directory
+        put_u16(&mut dir, count);
+        put_u16(&mut dir, count);
+        put_u32(&mut dir, dir_size);
+        put_u32(&mut dir, dir_offset);
+        put_u16(&mut dir, 0); // comment length
+
+        self.w.write_all(&dir)?;
+        Ok(self.w)
+    }
+}
+
+fn to_u32(x: u64) -> io::Result<u32> {
+    if x > u32::MAX as u64 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "archive too large for zip"));
+    }
+    Ok(x as u32)
+}
+
+fn put_u16(v: &mut Vec<u8>, x: u16) {
+    v.extend_from_slice(&x.to_le_bytes());
+}
+
+fn put_u32(v: &mut Vec<u8>, x: u32) {
+    v.extend_from_slice(&x.to_le_bytes());
+}
+
+/// Lookup table for `crc32`, one entry per byte value.
+static CRC_TABLE: [u32; 256] = make_crc_table();
+
+const fn make_crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 { 0xedb88320 ^ (c >> 1) } else { c >> 1 };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+}
+
+/// CRC-32 (as used by zip and PNG) of `data`.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in data {
+        crc = CRC_TABLE[((crc ^ b as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::crc32;
+
+    #[test]
+    fn crc32_check_values() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+        assert_eq!(crc32(b"The quick brown fox jumps over the lazy dog"), 0x414fa339);
+    }
+}